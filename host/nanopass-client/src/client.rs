@@ -0,0 +1,659 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Commands of the application.
+//!
+//! Each APDU is sent by a method of [`Client`]. Procedures spanning several
+//! APDUs, such as export and import, are driven by a dedicated type which
+//! borrows the client until they are complete.
+
+use crate::transport::Transport;
+use crate::{Error, Instruction, Layout, Protection, Status, CLA, FIELD_LEN};
+
+/// Fields changed by the Update APDU, as a mask in P1.
+const UPDATE_LOGIN: u8 = 0x01;
+const UPDATE_PASSWORD: u8 = 0x02;
+const UPDATE_GENERATE: u8 = 0x04;
+const UPDATE_PROTECTION: u8 = 0x08;
+const UPDATE_TAGS: u8 = 0x10;
+
+/// Typing options of the Type APDU, as a mask in P1.
+const TYPE_LOGIN_TAB: u8 = 0x01;
+const TYPE_ENTER: u8 = 0x02;
+
+/// Length of SHA-1 hashes sent by the BreachNext APDU.
+pub const HASH_LEN: usize = 20;
+/// Maximum number of hashes sent by one BreachNext APDU.
+const HASHES_PER_APDU: usize = 12;
+
+/// Password of a new or updated entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Password<'a> {
+    /// The password is supplied by the host.
+    Supplied(&'a str),
+    /// The password is generated by the device.
+    Generated,
+}
+
+/// Changed fields of an entry, for [`Client::update`]. Fields set to `None`
+/// are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Update<'a> {
+    pub login: Option<&'a str>,
+    pub password: Option<Password<'a>>,
+    pub protection: Option<Protection>,
+    pub tags: Option<u8>,
+}
+
+/// Login and password of an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub login: String,
+    pub password: String,
+}
+
+/// Usage of the storage by the active vault, counted in storage units. Each
+/// entry uses 1 to 3 units. Units used by other vaults are counted as free.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    pub used: u32,
+    pub free: u32,
+    pub capacity: u32,
+    /// Highest number of writes of a unit.
+    pub max_writes: u32,
+    /// Estimated number of writes remaining before units wear out.
+    pub remaining_writes: u32,
+}
+
+/// Metadata of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Creation time, as a Unix timestamp rounded down to the day, or 0 if
+    /// unknown.
+    pub created: u32,
+    /// Modification time, in the same format.
+    pub updated: u32,
+    /// Number of times the password has been read.
+    pub accesses: u32,
+    /// Time of the last change of the password, in the same format as the
+    /// creation time.
+    pub changed: u32,
+}
+
+/// Encodes a name, login or password, padded with zeros.
+fn field(value: &str, name: &'static str) -> Result<[u8; FIELD_LEN], Error> {
+    if value.len() > FIELD_LEN || value.contains('\0') {
+        return Err(Error::InvalidField(name));
+    }
+    let mut bytes = [0; FIELD_LEN];
+    bytes[..value.len()].copy_from_slice(value.as_bytes());
+    Ok(bytes)
+}
+
+/// Decodes a name, login or password padded with zeros.
+fn from_field(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// Decodes the big-endian integers of a response of the expected length.
+fn u32_values<const N: usize>(response: &[u8]) -> Result<[u32; N], Error> {
+    if response.len() != 4 * N {
+        return Err(Error::InvalidResponse("unexpected length"));
+    }
+    let mut values = [0; N];
+    for (value, bytes) in values.iter_mut().zip(response.chunks_exact(4)) {
+        *value = u32::from_be_bytes(bytes.try_into().unwrap());
+    }
+    Ok(values)
+}
+
+/// The NanoPass application.
+pub struct Client<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> Client<T> {
+    pub fn new(transport: T) -> Client<T> {
+        Client { transport }
+    }
+
+    /// Returns the transport, for instance to check a mock.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Sends a command to the application and returns its response data.
+    /// The data must not be longer than 255 bytes.
+    pub fn command(
+        &mut self,
+        ins: Instruction,
+        p1: u8,
+        p2: u8,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let len = u8::try_from(data.len()).map_err(|_| Error::DataTooLong(data.len()))?;
+        let mut apdu = vec![CLA, ins as u8, p1, p2, len];
+        apdu.extend_from_slice(data);
+        let mut response = self.transport.exchange(&apdu)?;
+        if response.len() < 2 {
+            return Err(Error::InvalidResponse("no status word"));
+        }
+        let sw = response.split_off(response.len() - 2);
+        match u16::from_be_bytes([sw[0], sw[1]]) {
+            Status::OK => Ok(response),
+            sw => Err(Error::Status(sw.into())),
+        }
+    }
+
+    /// Returns the version of the application, such as "1.4.0".
+    pub fn version(&mut self) -> Result<String, Error> {
+        // Format, then name and version prefixed by their length
+        let response = self.command(Instruction::GetVersion, 0, 0, &[])?;
+        let invalid = || Error::InvalidResponse("invalid version");
+        let name_len = *response.get(1).ok_or_else(invalid)? as usize;
+        let version_len = *response.get(2 + name_len).ok_or_else(invalid)? as usize;
+        let version = response
+            .get(3 + name_len..3 + name_len + version_len)
+            .ok_or_else(invalid)?;
+        Ok(String::from_utf8_lossy(version).into_owned())
+    }
+
+    /// Returns the number of entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `tags` - If not zero, only entries having at least one of the tags of
+    ///   this mask are counted.
+    pub fn size(&mut self, tags: u8) -> Result<u32, Error> {
+        let response = self.command(Instruction::GetSize, tags, 0, &[])?;
+        match response.len() {
+            4 => Ok(u32_values::<1>(&response)?[0]),
+            _ => Ok(u32_values::<2>(&response)?[0]),
+        }
+    }
+
+    /// Returns the number of entries the storage can hold, at least, or None
+    /// for versions prior to 1.4.0.
+    pub fn capacity(&mut self) -> Result<Option<u32>, Error> {
+        let response = self.command(Instruction::GetSize, 0, 0, &[])?;
+        match response.len() {
+            4 => Ok(None),
+            _ => Ok(Some(u32_values::<2>(&response)?[1])),
+        }
+    }
+
+    /// Adds an entry. Requires user consent.
+    pub fn add(
+        &mut self,
+        name: &str,
+        login: &str,
+        password: Password,
+        protection: Protection,
+        tags: u8,
+    ) -> Result<(), Error> {
+        let mut data = field(name, "name")?.to_vec();
+        data.extend_from_slice(&field(login, "login")?);
+        let p1 = match password {
+            Password::Supplied(password) => {
+                data.extend_from_slice(&field(password, "password")?);
+                0
+            }
+            Password::Generated => 1,
+        };
+        data.push(tags);
+        self.command(Instruction::Add, p1, protection as u8, &data)?;
+        Ok(())
+    }
+
+    /// Returns the name of the entry at an index.
+    ///
+    /// # Arguments
+    ///
+    /// * `tags` - If not zero, the index is among the entries having at least
+    ///   one of the tags of this mask.
+    pub fn name(&mut self, index: u32, tags: u8) -> Result<String, Error> {
+        let response = self.command(Instruction::GetName, tags, 0, &index.to_be_bytes())?;
+        if response.len() != FIELD_LEN {
+            return Err(Error::InvalidResponse("unexpected length"));
+        }
+        Ok(from_field(&response))
+    }
+
+    /// Returns the names of all entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `tags` - If not zero, only entries having at least one of the tags of
+    ///   this mask are listed.
+    pub fn names(&mut self, tags: u8) -> Result<Vec<String>, Error> {
+        (0..self.size(tags)?).map(|i| self.name(i, tags)).collect()
+    }
+
+    /// Returns the login and password of an entry. Requires user consent,
+    /// according to the protection level of the entry.
+    pub fn get(&mut self, name: &str) -> Result<Credentials, Error> {
+        let response = self.command(Instruction::GetByName, 0, 0, &field(name, "name")?)?;
+        if response.len() < FIELD_LEN {
+            return Err(Error::InvalidResponse("unexpected length"));
+        }
+        Ok(Credentials {
+            login: from_field(&response[..FIELD_LEN]),
+            password: from_field(&response[FIELD_LEN..]),
+        })
+    }
+
+    /// Deletes an entry. Requires user consent.
+    pub fn delete(&mut self, name: &str) -> Result<(), Error> {
+        self.command(Instruction::DeleteByName, 0, 0, &field(name, "name")?)?;
+        Ok(())
+    }
+
+    /// Starts an export. Requires user consent.
+    ///
+    /// # Arguments
+    ///
+    /// * `encrypt` - Encrypt entries with the export key of the vault, so that
+    ///   only a device with the same seed can import them.
+    /// * `tags` - If not zero, only entries having at least one of the tags of
+    ///   this mask are exported.
+    pub fn export(&mut self, encrypt: bool, tags: u8) -> Result<Export<'_, T>, Error> {
+        let response = self.command(Instruction::Export, encrypt as u8, tags, &[])?;
+        let [remaining] = u32_values(&response)?;
+        Ok(Export {
+            client: self,
+            remaining,
+        })
+    }
+
+    /// Starts an import. Requires user consent. Imported entries replace the
+    /// entries with the same name, keeping their protection level. Replacing
+    /// an entry protected by double confirmation or display-only requires
+    /// user consent again.
+    ///
+    /// # Arguments
+    ///
+    /// * `encrypted` - Entries have been exported with encryption.
+    /// * `count` - Number of entries to import.
+    pub fn import(&mut self, encrypted: bool, count: u32) -> Result<Import<'_, T>, Error> {
+        self.command(
+            Instruction::Import,
+            encrypted as u8,
+            0,
+            &count.to_be_bytes(),
+        )?;
+        Ok(Import {
+            client: self,
+            remaining: count,
+        })
+    }
+
+    /// Deletes all entries of the vault. Requires user consent.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.command(Instruction::Clear, 0, 0, &[])?;
+        Ok(())
+    }
+
+    /// Quits the application, returning to the dashboard.
+    pub fn quit(&mut self) -> Result<(), Error> {
+        match self.command(Instruction::Quit, 0, 0, &[]) {
+            // The device may disconnect before responding.
+            Err(Error::Io(_)) => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Displays the login and password of an entry on the device. Requires
+    /// user consent.
+    ///
+    /// # Arguments
+    ///
+    /// * `qr` - Display the password as a QR code. Not supported on the Nano
+    ///   S.
+    pub fn show(&mut self, name: &str, qr: bool) -> Result<(), Error> {
+        self.command(
+            Instruction::ShowOnScreen,
+            qr as u8,
+            0,
+            &field(name, "name")?,
+        )?;
+        Ok(())
+    }
+
+    /// Tells if an entry exists.
+    pub fn has_name(&mut self, name: &str) -> Result<bool, Error> {
+        match self
+            .command(Instruction::HasName, 0, 0, &field(name, "name")?)?
+            .as_slice()
+        {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(Error::InvalidResponse("invalid boolean")),
+        }
+    }
+
+    /// Types the password of an entry, the device acting as a USB keyboard.
+    /// Requires user consent.
+    ///
+    /// # Arguments
+    ///
+    /// * `login` - Type the login and a tab before the password.
+    /// * `enter` - Press Enter after the password.
+    pub fn type_password(
+        &mut self,
+        name: &str,
+        layout: Layout,
+        login: bool,
+        enter: bool,
+    ) -> Result<(), Error> {
+        let mut options = 0;
+        if login {
+            options |= TYPE_LOGIN_TAB;
+        }
+        if enter {
+            options |= TYPE_ENTER;
+        }
+        self.command(
+            Instruction::Type,
+            options,
+            layout as u8,
+            &field(name, "name")?,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the usage of the storage by the active vault.
+    pub fn storage_stats(&mut self) -> Result<StorageStats, Error> {
+        let response = self.command(Instruction::GetStorageStats, 0, 0, &[])?;
+        let [used, free, capacity, max_writes, remaining_writes] = u32_values(&response)?;
+        Ok(StorageStats {
+            used,
+            free,
+            capacity,
+            max_writes,
+            remaining_writes,
+        })
+    }
+
+    /// Sets the current time of the device, used to date created and updated
+    /// entries. The device has no clock, so this must be done before
+    /// modifying entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - Seconds since the Unix epoch.
+    pub fn set_time(&mut self, now: u32) -> Result<(), Error> {
+        self.command(Instruction::SetTime, 0, 0, &now.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the metadata of an entry.
+    pub fn metadata(&mut self, name: &str) -> Result<Metadata, Error> {
+        let response = self.command(Instruction::GetMetadata, 0, 0, &field(name, "name")?)?;
+        let [created, updated, accesses, changed] = u32_values(&response)?;
+        Ok(Metadata {
+            created,
+            updated,
+            accesses,
+            changed,
+        })
+    }
+
+    /// Returns the names of the entries whose password has not been changed
+    /// for longer than the rotation age configured on the device. The time must
+    /// have been set first.
+    ///
+    /// # Arguments
+    ///
+    /// * `tags` - If not zero, only entries having at least one of the tags of
+    ///   this mask are listed.
+    pub fn stale_names(&mut self, tags: u8) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        loop {
+            let index = names.len() as u32;
+            let response = self.command(Instruction::GetStale, tags, 0, &index.to_be_bytes())?;
+            // Count, followed by the name if the index is in range
+            let (count, name) = response.split_at(4.min(response.len()));
+            let [count] = u32_values(count)?;
+            if names.len() as u32 >= count {
+                return Ok(names);
+            }
+            if name.len() != FIELD_LEN {
+                return Err(Error::InvalidResponse("unexpected length"));
+            }
+            names.push(from_field(name));
+        }
+    }
+
+    /// Audits the passwords on the device, which are never returned. Requires
+    /// user consent. Display-only entries are not audited.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - Minimum entropy of a strong password, in bits. If zero,
+    ///   the default threshold of the device is used.
+    ///
+    /// Returns the names of the entries having findings, with their findings
+    /// as a combination of `AUDIT_WEAK` and `AUDIT_REUSED`.
+    pub fn audit(&mut self, threshold: u8) -> Result<Vec<(String, u8)>, Error> {
+        let names = self.names(0)?;
+        let mut findings = Vec::new();
+        while findings.len() < names.len() {
+            let index = findings.len() as u32;
+            let response = self.command(Instruction::Audit, 0, threshold, &index.to_be_bytes())?;
+            if response.is_empty() {
+                return Err(Error::InvalidResponse("no audit findings"));
+            }
+            findings.extend_from_slice(&response);
+        }
+        if findings.len() != names.len() {
+            return Err(Error::InvalidResponse("too many audit findings"));
+        }
+        Ok(names
+            .into_iter()
+            .zip(findings)
+            .filter(|&(_, f)| f != 0)
+            .collect())
+    }
+
+    /// Finds the entries sharing their password, which are compared on the
+    /// device and never returned. Requires user consent. Display-only entries
+    /// are not reported.
+    ///
+    /// Returns groups of names of entries having the same password.
+    pub fn duplicates(&mut self) -> Result<Vec<Vec<String>>, Error> {
+        let response = self.command(Instruction::Duplicates, 0, 0, &[])?;
+        let [count] = u32_values(&response)?;
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        for _ in 0..count {
+            let response = self.command(Instruction::DuplicatesNext, 0, 0, &[])?;
+            if response.len() != FIELD_LEN + 2 {
+                return Err(Error::InvalidResponse("unexpected length"));
+            }
+            let group = u16::from_be_bytes([response[FIELD_LEN], response[FIELD_LEN + 1]]);
+            // Entries of a group are returned consecutively.
+            match group as usize {
+                g if g == groups.len() => groups.push(Vec::new()),
+                g if g + 1 == groups.len() => (),
+                _ => return Err(Error::InvalidResponse("unexpected group")),
+            }
+            groups
+                .last_mut()
+                .unwrap()
+                .push(from_field(&response[..FIELD_LEN]));
+        }
+        Ok(groups)
+    }
+
+    /// Starts the breach check of an entry. Requires user consent, according
+    /// to the protection level of the entry. Display-only entries cannot be
+    /// checked.
+    pub fn breach_check(&mut self, name: &str) -> Result<BreachCheck<'_, T>, Error> {
+        let response = self.command(Instruction::BreachCheck, 0, 0, &field(name, "name")?)?;
+        let prefix = String::from_utf8(response)
+            .ok()
+            .filter(|p| p.len() == 5 && p.bytes().all(|c| c.is_ascii_hexdigit()))
+            .ok_or(Error::InvalidResponse("invalid hash prefix"))?;
+        Ok(BreachCheck {
+            client: self,
+            prefix,
+        })
+    }
+
+    /// Renames an entry, keeping its other fields. Requires user consent.
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), Error> {
+        let mut data = field(name, "name")?.to_vec();
+        data.extend_from_slice(&field(new_name, "new name")?);
+        self.command(Instruction::Rename, 0, 0, &data)?;
+        Ok(())
+    }
+
+    /// Changes some fields of an entry. Requires user consent for each
+    /// changed field, and the confirmations of the new protection level if it
+    /// requires more. The protection of display-only entries cannot be
+    /// lowered. The time should have been set first.
+    pub fn update(&mut self, name: &str, update: &Update) -> Result<(), Error> {
+        let mut mask = 0;
+        let mut data = field(name, "name")?.to_vec();
+        if let Some(login) = update.login {
+            mask |= UPDATE_LOGIN;
+            data.extend_from_slice(&field(login, "login")?);
+        }
+        match update.password {
+            Some(Password::Supplied(password)) => {
+                mask |= UPDATE_PASSWORD;
+                data.extend_from_slice(&field(password, "password")?);
+            }
+            Some(Password::Generated) => mask |= UPDATE_GENERATE,
+            None => (),
+        }
+        if let Some(protection) = update.protection {
+            mask |= UPDATE_PROTECTION;
+            data.push(protection as u8);
+        }
+        if let Some(tags) = update.tags {
+            mask |= UPDATE_TAGS;
+            data.push(tags);
+        }
+        self.command(Instruction::Update, mask, 0, &data)?;
+        Ok(())
+    }
+}
+
+/// Export in progress. Entries are retrieved one at a time with the
+/// ExportNext APDU, by iterating over it.
+pub struct Export<'a, T: Transport> {
+    client: &'a mut Client<T>,
+    remaining: u32,
+}
+
+impl<T: Transport> Export<'_, T> {
+    /// Number of entries still to be retrieved.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+}
+
+impl<T: Transport> Iterator for Export<'_, T> {
+    /// Entry as returned by the application: name, login, password and
+    /// attributes, encrypted or not.
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let entry = self.client.command(Instruction::ExportNext, 0, 0, &[]);
+        if entry.is_err() {
+            // The application has left the export procedure.
+            self.remaining = 0;
+        }
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+/// Import in progress. Entries are sent one at a time with the ImportNext
+/// APDU.
+pub struct Import<'a, T: Transport> {
+    client: &'a mut Client<T>,
+    remaining: u32,
+}
+
+impl<T: Transport> Import<'_, T> {
+    /// Number of entries still to be sent.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Imports the next entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - Entry as returned by an export, encrypted or not depending
+    ///   on how the import has been started.
+    ///
+    /// # Panics
+    ///
+    /// Panics if all the announced entries have already been sent.
+    pub fn send(&mut self, entry: &[u8]) -> Result<(), Error> {
+        assert!(self.remaining > 0, "all entries have been imported");
+        self.remaining -= 1;
+        match self.client.command(Instruction::ImportNext, 0, 0, entry) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // The application has left the import procedure.
+                self.remaining = 0;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Breach check in progress.
+pub struct BreachCheck<'a, T: Transport> {
+    client: &'a mut Client<T>,
+    prefix: String,
+}
+
+impl<T: Transport> BreachCheck<'_, T> {
+    /// First 5 digits of the SHA-1 hash of the password, in upper case
+    /// hexadecimal, to be sent to a range API of breached passwords.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Sends the hashes of the breached passwords starting with the prefix.
+    /// Whether the password is among them is only displayed on the device.
+    pub fn finish(self, hashes: &[[u8; HASH_LEN]]) -> Result<(), Error> {
+        let mut chunks: Vec<_> = hashes.chunks(HASHES_PER_APDU).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let last = chunks.len() - 1;
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            self.client.command(
+                Instruction::BreachNext,
+                (i == last) as u8,
+                0,
+                &chunk.concat(),
+            )?;
+        }
+        Ok(())
+    }
+}
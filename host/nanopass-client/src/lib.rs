@@ -0,0 +1,279 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client of the NanoPass application.
+//!
+//! [`Client`] sends the APDUs documented in `doc/impl.md` through a
+//! [`Transport`](transport::Transport): a device plugged over USB, the
+//! Speculos emulator, or a scripted stand-in for tests.
+
+pub mod client;
+pub mod transport;
+
+pub use client::Client;
+
+use std::fmt;
+use std::io;
+
+/// Class of all the APDUs of the application.
+pub const CLA: u8 = 0x80;
+
+/// Length of names, logins and passwords, padded with zeros.
+pub const FIELD_LEN: usize = 32;
+
+/// Length of the attributes block of exported and imported entries.
+pub const ATTRIBUTES_LEN: usize = 32;
+
+/// Tags an entry can be labeled with. Tags are encoded as a bit mask, bit `i`
+/// corresponding to `TAGS[i]`.
+pub const TAGS: [&str; 8] = [
+    "work", "personal", "infra", "banking", "social", "shopping", "dev", "other",
+];
+
+/// Instructions of the application, with their INS byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Instruction {
+    GetVersion = 0x01,
+    GetSize = 0x02,
+    Add = 0x03,
+    GetName = 0x04,
+    GetByName = 0x05,
+    DeleteByName = 0x06,
+    Export = 0x07,
+    ExportNext = 0x08,
+    Import = 0x09,
+    ImportNext = 0x0a,
+    Clear = 0x0b,
+    Quit = 0x0c,
+    ShowOnScreen = 0x0d,
+    HasName = 0x0e,
+    Type = 0x0f,
+    GetStorageStats = 0x10,
+    SetTime = 0x11,
+    GetMetadata = 0x12,
+    GetStale = 0x13,
+    Audit = 0x14,
+    Duplicates = 0x15,
+    DuplicatesNext = 0x16,
+    BreachCheck = 0x17,
+    BreachNext = 0x18,
+    Rename = 0x19,
+    Update = 0x1a,
+}
+
+impl TryFrom<u8> for Instruction {
+    type Error = ();
+
+    fn try_from(ins: u8) -> Result<Self, Self::Error> {
+        match ins {
+            0x01 => Ok(Self::GetVersion),
+            0x02 => Ok(Self::GetSize),
+            0x03 => Ok(Self::Add),
+            0x04 => Ok(Self::GetName),
+            0x05 => Ok(Self::GetByName),
+            0x06 => Ok(Self::DeleteByName),
+            0x07 => Ok(Self::Export),
+            0x08 => Ok(Self::ExportNext),
+            0x09 => Ok(Self::Import),
+            0x0a => Ok(Self::ImportNext),
+            0x0b => Ok(Self::Clear),
+            0x0c => Ok(Self::Quit),
+            0x0d => Ok(Self::ShowOnScreen),
+            0x0e => Ok(Self::HasName),
+            0x0f => Ok(Self::Type),
+            0x10 => Ok(Self::GetStorageStats),
+            0x11 => Ok(Self::SetTime),
+            0x12 => Ok(Self::GetMetadata),
+            0x13 => Ok(Self::GetStale),
+            0x14 => Ok(Self::Audit),
+            0x15 => Ok(Self::Duplicates),
+            0x16 => Ok(Self::DuplicatesNext),
+            0x17 => Ok(Self::BreachCheck),
+            0x18 => Ok(Self::BreachNext),
+            0x19 => Ok(Self::Rename),
+            0x1a => Ok(Self::Update),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Status words returned by the application, other than success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The user refused the operation on the device.
+    NoConsent,
+    StorageFull,
+    EntryNotFound,
+    /// An imported entry does not match its MAC.
+    DecryptFailed,
+    /// The entry can only be displayed on the device.
+    DisplayOnly,
+    /// A character cannot be typed with the requested keyboard layout.
+    CannotType,
+    /// An entry with the same name already exists.
+    NameExists,
+    BadCla,
+    BadLen,
+    /// Unknown instruction, or instruction unexpected in the current state.
+    Unknown,
+    Other(u16),
+}
+
+impl Status {
+    /// Status word of success.
+    pub const OK: u16 = 0x9000;
+
+    pub fn sw(self) -> u16 {
+        match self {
+            Status::NoConsent => 0x69f0,
+            Status::StorageFull => 0x9210,
+            Status::EntryNotFound => 0x6a88,
+            Status::DecryptFailed => 0x9d60,
+            Status::DisplayOnly => 0x69f1,
+            Status::CannotType => 0x6a8a,
+            Status::NameExists => 0x6a89,
+            Status::BadCla => 0x6e00,
+            Status::BadLen => 0x6e01,
+            Status::Unknown => 0x6d00,
+            Status::Other(sw) => sw,
+        }
+    }
+}
+
+impl From<u16> for Status {
+    fn from(sw: u16) -> Status {
+        match sw {
+            0x69f0 => Status::NoConsent,
+            0x9210 => Status::StorageFull,
+            0x6a88 => Status::EntryNotFound,
+            0x9d60 => Status::DecryptFailed,
+            0x69f1 => Status::DisplayOnly,
+            0x6a8a => Status::CannotType,
+            0x6a89 => Status::NameExists,
+            0x6e00 => Status::BadCla,
+            0x6e01 => Status::BadLen,
+            0x6d00 => Status::Unknown,
+            sw => Status::Other(sw),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Status::NoConsent => "operation cancelled on the device",
+            Status::StorageFull => "storage full",
+            Status::EntryNotFound => "entry not found",
+            Status::DecryptFailed => "decryption failed",
+            Status::DisplayOnly => "entry can only be displayed on the device",
+            Status::CannotType => "character cannot be typed with this keyboard layout",
+            Status::NameExists => "an entry with this name already exists",
+            Status::BadCla => "bad class",
+            Status::BadLen => "bad length",
+            Status::Unknown => "unknown or unexpected instruction",
+            Status::Other(_) => "device returned status word",
+        };
+        write!(f, "{} (status word {:04x})", description, self.sw())
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The application responded with an error status word.
+    Status(Status),
+    /// The response of the application is not as expected.
+    InvalidResponse(&'static str),
+    /// A name, login or password is longer than `FIELD_LEN` bytes, or
+    /// contains a null byte.
+    InvalidField(&'static str),
+    /// The data of a command is longer than 255 bytes, the maximum length of
+    /// an APDU.
+    DataTooLong(usize),
+    /// The operation is not supported by this version of the application.
+    Unsupported(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Status(status) => write!(f, "{}", status),
+            Error::InvalidResponse(message) => write!(f, "invalid response: {}", message),
+            Error::InvalidField(field) => write!(
+                f,
+                "{} is longer than {} bytes or contains a null byte",
+                field, FIELD_LEN
+            ),
+            Error::DataTooLong(len) => {
+                write!(f, "command data is {} bytes long, at most 255 allowed", len)
+            }
+            Error::Unsupported(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Protection level of an entry, deciding which consent is needed to access
+/// it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Protection {
+    /// The user confirms each access.
+    #[default]
+    Confirm = 0,
+    /// No confirmation is needed.
+    NoConsent = 1,
+    /// The user confirms each access twice.
+    DoubleConfirm = 2,
+    /// The password can only be displayed on the device.
+    DisplayOnly = 3,
+}
+
+impl TryFrom<u8> for Protection {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Self::Confirm),
+            1 => Ok(Self::NoConsent),
+            2 => Ok(Self::DoubleConfirm),
+            3 => Ok(Self::DisplayOnly),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Keyboard layout of the host, for the Type APDU.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    #[default]
+    Us = 0,
+    Fr = 1,
+    De = 2,
+}
+
+/// Audit finding: the password is weak.
+pub const AUDIT_WEAK: u8 = 0x01;
+/// Audit finding: another entry of the vault has the same password.
+pub const AUDIT_REUSED: u8 = 0x02;
@@ -0,0 +1,200 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transports exchanging APDUs with the application, running on a device or
+//! on the Speculos emulator.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// Exchanges APDUs with the application.
+pub trait Transport {
+    /// Sends a command APDU and returns the response, status word included.
+    fn exchange(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn exchange(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>> {
+        (**self).exchange(apdu)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn exchange(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>> {
+        (**self).exchange(apdu)
+    }
+}
+
+/// Application running on the Speculos emulator, reached through its APDU TCP
+/// port (9999 by default).
+pub struct Speculos {
+    stream: TcpStream,
+}
+
+impl Speculos {
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Speculos> {
+        Ok(Speculos {
+            stream: TcpStream::connect(address)?,
+        })
+    }
+}
+
+impl Transport for Speculos {
+    fn exchange(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>> {
+        // Commands and responses are prefixed by their length. The length of a
+        // response does not count its status word.
+        self.stream.write_all(&(apdu.len() as u32).to_be_bytes())?;
+        self.stream.write_all(apdu)?;
+        let mut len = [0; 4];
+        self.stream.read_exact(&mut len)?;
+        let mut response = vec![0; u32::from_be_bytes(len) as usize + 2];
+        self.stream.read_exact(&mut response)?;
+        Ok(response)
+    }
+}
+
+/// Device plugged over USB, reached through the Linux hidraw interface.
+#[cfg(target_os = "linux")]
+pub struct Hid {
+    file: std::fs::File,
+}
+
+#[cfg(target_os = "linux")]
+impl Hid {
+    /// USB vendor identifier of Ledger devices.
+    const VENDOR_ID: &'static str = "00002C97";
+    /// The interface exchanging APDUs has this vendor-defined usage page.
+    const USAGE_PAGE: [u8; 3] = [0x06, 0xa0, 0xff];
+    const REPORT_LEN: usize = 64;
+    const CHANNEL: u16 = 0x0101;
+    const TAG_APDU: u8 = 0x05;
+
+    /// Opens the first Ledger device found.
+    pub fn open() -> io::Result<Hid> {
+        for node in std::fs::read_dir("/sys/class/hidraw")? {
+            let node = node?;
+            let device = node.path().join("device");
+            let uevent = std::fs::read_to_string(device.join("uevent")).unwrap_or_default();
+            let descriptor = std::fs::read(device.join("report_descriptor")).unwrap_or_default();
+            if uevent.contains(&format!("HID_ID=0003:{}:", Hid::VENDOR_ID))
+                && descriptor.starts_with(&Hid::USAGE_PAGE)
+            {
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(std::path::Path::new("/dev").join(node.file_name()))?;
+                return Ok(Hid { file });
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no Ledger device found",
+        ))
+    }
+
+    fn invalid(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Transport for Hid {
+    fn exchange(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>> {
+        // APDUs are split in reports made of the channel, the tag and a
+        // sequence number. The first report also holds the length of the APDU.
+        let mut message = (apdu.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(apdu);
+        for (seq, chunk) in message.chunks(Hid::REPORT_LEN - 5).enumerate() {
+            // The report number, zero, is written first.
+            let mut report = [0; Hid::REPORT_LEN + 1];
+            report[1..3].copy_from_slice(&Hid::CHANNEL.to_be_bytes());
+            report[3] = Hid::TAG_APDU;
+            report[4..6].copy_from_slice(&(seq as u16).to_be_bytes());
+            report[6..6 + chunk.len()].copy_from_slice(chunk);
+            self.file.write_all(&report)?;
+        }
+
+        let mut response = Vec::new();
+        let mut expected = None;
+        for seq in 0u16.. {
+            let mut report = [0; Hid::REPORT_LEN];
+            let len = self.file.read(&mut report)?;
+            if len < 5
+                || report[..2] != Hid::CHANNEL.to_be_bytes()
+                || report[2] != Hid::TAG_APDU
+                || report[3..5] != seq.to_be_bytes()
+            {
+                return Err(Hid::invalid("unexpected HID report"));
+            }
+            response.extend_from_slice(&report[5..len]);
+            if expected.is_none() && response.len() >= 2 {
+                expected = Some(u16::from_be_bytes([response[0], response[1]]) as usize + 2);
+            }
+            if let Some(expected) = expected.filter(|&e| response.len() >= e) {
+                response.truncate(expected);
+                break;
+            }
+        }
+        Ok(response.split_off(2))
+    }
+}
+
+/// Scripted stand-in for the application, for tests. Each command must be the
+/// next expected one, and gets the response it has been scripted with.
+#[derive(Debug, Default)]
+pub struct Mock {
+    script: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Mock {
+    pub fn new() -> Mock {
+        Mock::default()
+    }
+
+    /// Expects a command APDU.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - Command APDU, header included.
+    /// * `data` - Data of the response.
+    /// * `sw` - Status word of the response.
+    pub fn expect(&mut self, command: &[u8], data: &[u8], sw: u16) -> &mut Mock {
+        let mut response = data.to_vec();
+        response.extend_from_slice(&sw.to_be_bytes());
+        self.script.push_back((command.to_vec(), response));
+        self
+    }
+
+    /// Tells if all the expected commands have been received.
+    pub fn is_done(&self) -> bool {
+        self.script.is_empty()
+    }
+}
+
+impl Transport for Mock {
+    fn exchange(&mut self, apdu: &[u8]) -> io::Result<Vec<u8>> {
+        match self.script.pop_front() {
+            Some((command, response)) if command == apdu => Ok(response),
+            expected => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unexpected APDU {:02x?}, expected {:02x?}",
+                    apdu,
+                    expected.map(|(command, _)| command)
+                ),
+            )),
+        }
+    }
+}
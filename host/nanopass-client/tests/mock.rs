@@ -0,0 +1,414 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nanopass_client::client::{Credentials, Metadata, Password, StorageStats, Update};
+use nanopass_client::transport::Mock;
+use nanopass_client::{Client, Error, Instruction, Layout, Protection, Status, AUDIT_WEAK};
+
+const OK: u16 = 0x9000;
+
+/// Command APDU of the application.
+fn apdu(ins: u8, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![0x80, ins, p1, p2, data.len() as u8];
+    apdu.extend_from_slice(data);
+    apdu
+}
+
+/// Field padded with zeros.
+fn field(value: &str) -> Vec<u8> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize(32, 0);
+    bytes
+}
+
+fn run<R>(mock: Mock, f: impl FnOnce(&mut Client<&mut Mock>) -> R) -> R {
+    let mut mock = mock;
+    let result = f(&mut Client::new(&mut mock));
+    assert!(mock.is_done(), "expected commands were not sent");
+    result
+}
+
+#[test]
+fn instructions() {
+    for ins in 0x01..=0x1a {
+        let instruction = Instruction::try_from(ins).unwrap();
+        assert_eq!(instruction as u8, ins);
+    }
+    assert!(Instruction::try_from(0x00).is_err());
+    assert!(Instruction::try_from(0x1b).is_err());
+}
+
+#[test]
+fn status_words() {
+    for sw in [
+        0x69f0, 0x9210, 0x6a88, 0x9d60, 0x69f1, 0x6a8a, 0x6a89, 0x6e00, 0x6e01, 0x6d00, 0x6982,
+    ] {
+        assert_eq!(Status::from(sw).sw(), sw);
+    }
+    assert_eq!(Status::from(0x69f0), Status::NoConsent);
+    assert_eq!(Status::from(0x6982), Status::Other(0x6982));
+    assert_eq!(
+        Status::NameExists.to_string(),
+        "an entry with this name already exists (status word 6a89)"
+    );
+}
+
+#[test]
+fn version() {
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x01, 0, 0, &[]), b"\x01\x08nanopass\x051.4.0\x00", OK);
+    assert_eq!(run(mock, |c| c.version().unwrap()), "1.4.0");
+
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x01, 0, 0, &[]), b"\x01\x08nano", OK);
+    assert!(matches!(
+        run(mock, |c| c.version()),
+        Err(Error::InvalidResponse(_))
+    ));
+}
+
+#[test]
+fn names() {
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x02, 0x01, 0, &[]), &[0, 0, 0, 2, 0, 0, 0, 128], OK)
+        .expect(&apdu(0x04, 0x01, 0, &[0, 0, 0, 0]), &field("gitlab"), OK)
+        .expect(&apdu(0x04, 0x01, 0, &[0, 0, 0, 1]), &field("vpn"), OK)
+        .expect(&apdu(0x02, 0, 0, &[]), &[0, 0, 0, 5, 0, 0, 0, 128], OK)
+        .expect(&apdu(0x02, 0, 0, &[]), &[0, 0, 0, 5], OK);
+    run(mock, |c| {
+        assert_eq!(c.names(0x01).unwrap(), ["gitlab", "vpn"]);
+        assert_eq!(c.capacity().unwrap(), Some(128));
+        // Versions prior to 1.4.0
+        assert_eq!(c.capacity().unwrap(), None);
+    });
+}
+
+#[test]
+fn add() {
+    let mut supplied = field("gitlab");
+    supplied.extend(field("alice"));
+    supplied.extend(field("epuu7Aeja9"));
+    supplied.push(0x41);
+    let mut generated = field("vpn");
+    generated.extend(field(""));
+    generated.push(0);
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x03, 0, 2, &supplied), &[], OK).expect(
+        &apdu(0x03, 1, 0, &generated),
+        &[],
+        0x69f0,
+    );
+    run(mock, |c| {
+        c.add(
+            "gitlab",
+            "alice",
+            Password::Supplied("epuu7Aeja9"),
+            Protection::DoubleConfirm,
+            0x41,
+        )
+        .unwrap();
+        assert!(matches!(
+            c.add("vpn", "", Password::Generated, Protection::Confirm, 0),
+            Err(Error::Status(Status::NoConsent))
+        ));
+        // Invalid fields are not sent
+        assert!(matches!(
+            c.add(
+                "a",
+                &"x".repeat(33),
+                Password::Generated,
+                Protection::Confirm,
+                0
+            ),
+            Err(Error::InvalidField("login"))
+        ));
+        assert!(matches!(
+            c.add("a\0b", "", Password::Generated, Protection::Confirm, 0),
+            Err(Error::InvalidField("name"))
+        ));
+    });
+}
+
+#[test]
+fn entries() {
+    let mut credentials = field("alice");
+    credentials.extend(field("epuu7Aeja9"));
+    let mut rename = field("gitlab");
+    rename.extend(field("gitlab.com"));
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x05, 0, 0, &field("gitlab")), &credentials, OK)
+        .expect(&apdu(0x0e, 0, 0, &field("gitlab")), &[1], OK)
+        .expect(&apdu(0x0e, 0, 0, &field("vpn")), &[0], OK)
+        .expect(&apdu(0x0d, 1, 0, &field("gitlab")), &[], OK)
+        .expect(&apdu(0x0f, 3, 1, &field("gitlab")), &[], 0x6a8a)
+        .expect(&apdu(0x19, 0, 0, &rename), &[], 0x6a89)
+        .expect(&apdu(0x06, 0, 0, &field("gitlab")), &[], OK)
+        .expect(&apdu(0x0b, 0, 0, &[]), &[], OK);
+    run(mock, |c| {
+        assert_eq!(
+            c.get("gitlab").unwrap(),
+            Credentials {
+                login: "alice".into(),
+                password: "epuu7Aeja9".into()
+            }
+        );
+        assert!(c.has_name("gitlab").unwrap());
+        assert!(!c.has_name("vpn").unwrap());
+        c.show("gitlab", true).unwrap();
+        assert!(matches!(
+            c.type_password("gitlab", Layout::Fr, true, true),
+            Err(Error::Status(Status::CannotType))
+        ));
+        assert!(matches!(
+            c.rename("gitlab", "gitlab.com"),
+            Err(Error::Status(Status::NameExists))
+        ));
+        c.delete("gitlab").unwrap();
+        c.clear().unwrap();
+    });
+}
+
+#[test]
+fn update() {
+    let mut all = field("gitlab");
+    all.extend(field("bob"));
+    all.extend(field("new password"));
+    all.extend([1, 0x40]);
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x1a, 0x1b, 0, &all), &[], OK).expect(
+        &apdu(0x1a, 0x04, 0, &field("gitlab")),
+        &[],
+        0x6a88,
+    );
+    run(mock, |c| {
+        let update = Update {
+            login: Some("bob"),
+            password: Some(Password::Supplied("new password")),
+            protection: Some(Protection::NoConsent),
+            tags: Some(0x40),
+        };
+        c.update("gitlab", &update).unwrap();
+        let update = Update {
+            password: Some(Password::Generated),
+            ..Default::default()
+        };
+        assert!(matches!(
+            c.update("gitlab", &update),
+            Err(Error::Status(Status::EntryNotFound))
+        ));
+    });
+}
+
+#[test]
+fn metadata() {
+    let stats: Vec<u8> = [10u32, 90, 100, 3, 99997]
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+    let metadata: Vec<u8> = [1615680000u32, 1654041600, 12, 1640995200]
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x10, 0, 0, &[]), &stats, OK)
+        .expect(&apdu(0x11, 0, 0, &1700000000u32.to_be_bytes()), &[], OK)
+        .expect(&apdu(0x12, 0, 0, &field("gitlab")), &metadata, OK)
+        .expect(&apdu(0x12, 0, 0, &field("vpn")), &metadata[..8], OK);
+    run(mock, |c| {
+        assert_eq!(
+            c.storage_stats().unwrap(),
+            StorageStats {
+                used: 10,
+                free: 90,
+                capacity: 100,
+                max_writes: 3,
+                remaining_writes: 99997
+            }
+        );
+        c.set_time(1700000000).unwrap();
+        assert_eq!(
+            c.metadata("gitlab").unwrap(),
+            Metadata {
+                created: 1615680000,
+                updated: 1654041600,
+                accesses: 12,
+                changed: 1640995200
+            }
+        );
+        assert!(matches!(c.metadata("vpn"), Err(Error::InvalidResponse(_))));
+    });
+}
+
+#[test]
+fn stale_names() {
+    let mut first = vec![0, 0, 0, 2];
+    first.extend(field("gitlab"));
+    let mut second = vec![0, 0, 0, 2];
+    second.extend(field("vpn"));
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x13, 0x08, 0, &[0, 0, 0, 0]), &first, OK)
+        .expect(&apdu(0x13, 0x08, 0, &[0, 0, 0, 1]), &second, OK)
+        .expect(&apdu(0x13, 0x08, 0, &[0, 0, 0, 2]), &[0, 0, 0, 2], OK)
+        .expect(&apdu(0x13, 0, 0, &[0, 0, 0, 0]), &[0, 0, 0, 0], OK);
+    run(mock, |c| {
+        assert_eq!(c.stale_names(0x08).unwrap(), ["gitlab", "vpn"]);
+        assert!(c.stale_names(0).unwrap().is_empty());
+    });
+}
+
+#[test]
+fn audit() {
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x02, 0, 0, &[]), &[0, 0, 0, 3, 0, 0, 0, 90], OK);
+    for (i, name) in ["a", "b", "c"].iter().enumerate() {
+        mock.expect(&apdu(0x04, 0, 0, &[0, 0, 0, i as u8]), &field(name), OK);
+    }
+    // Findings may be returned in several APDUs
+    mock.expect(&apdu(0x14, 0, 60, &[0, 0, 0, 0]), &[0, 3], OK)
+        .expect(&apdu(0x14, 0, 60, &[0, 0, 0, 2]), &[1], OK);
+    let findings = run(mock, |c| c.audit(60).unwrap());
+    assert_eq!(
+        findings,
+        [("b".to_string(), 3), ("c".to_string(), AUDIT_WEAK)]
+    );
+}
+
+#[test]
+fn duplicates() {
+    let next = |name: &str, group: u16| {
+        let mut response = field(name);
+        response.extend(group.to_be_bytes());
+        response
+    };
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x15, 0, 0, &[]), &[0, 0, 0, 4], OK)
+        .expect(&apdu(0x16, 0, 0, &[]), &next("a", 0), OK)
+        .expect(&apdu(0x16, 0, 0, &[]), &next("b", 0), OK)
+        .expect(&apdu(0x16, 0, 0, &[]), &next("c", 1), OK)
+        .expect(&apdu(0x16, 0, 0, &[]), &next("d", 1), OK);
+    assert_eq!(
+        run(mock, |c| c.duplicates().unwrap()),
+        [vec!["a", "b"], vec!["c", "d"]]
+    );
+}
+
+#[test]
+fn breach_check() {
+    let hashes: Vec<[u8; 20]> = (0..13).map(|i| [i; 20]).collect();
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x17, 0, 0, &field("gitlab")), b"0A1B2", OK)
+        .expect(&apdu(0x18, 0, 0, &hashes[..12].concat()), &[], OK)
+        .expect(&apdu(0x18, 1, 0, &hashes[12]), &[], OK)
+        .expect(&apdu(0x17, 0, 0, &field("vpn")), b"FFFFF", OK)
+        .expect(&apdu(0x18, 1, 0, &[]), &[], OK)
+        .expect(&apdu(0x17, 0, 0, &field("pin")), &[], 0x69f1);
+    run(mock, |c| {
+        let check = c.breach_check("gitlab").unwrap();
+        assert_eq!(check.prefix(), "0A1B2");
+        check.finish(&hashes).unwrap();
+        c.breach_check("vpn").unwrap().finish(&[]).unwrap();
+        assert!(matches!(
+            c.breach_check("pin"),
+            Err(Error::Status(Status::DisplayOnly))
+        ));
+    });
+}
+
+#[test]
+fn export() {
+    let entries = [vec![1; 128], vec![2; 128]];
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x07, 0, 0x02, &[]), &[0, 0, 0, 2], OK)
+        .expect(&apdu(0x08, 0, 0, &[]), &entries[0], OK)
+        .expect(&apdu(0x08, 0, 0, &[]), &entries[1], OK)
+        .expect(&apdu(0x07, 1, 0, &[]), &[], 0x69f0);
+    run(mock, |c| {
+        let export = c.export(false, 0x02).unwrap();
+        assert_eq!(export.remaining(), 2);
+        let exported: Result<Vec<_>, _> = export.collect();
+        assert_eq!(exported.unwrap(), entries);
+        assert!(matches!(
+            c.export(true, 0),
+            Err(Error::Status(Status::NoConsent))
+        ));
+    });
+}
+
+#[test]
+fn export_interrupted() {
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x07, 1, 0, &[]), &[0, 0, 0, 3], OK)
+        .expect(&apdu(0x08, 0, 0, &[]), &[0; 160], OK)
+        .expect(&apdu(0x08, 0, 0, &[]), &[], 0x6d00);
+    run(mock, |c| {
+        let mut export = c.export(true, 0).unwrap();
+        assert!(export.next().unwrap().is_ok());
+        assert!(matches!(
+            export.next(),
+            Some(Err(Error::Status(Status::Unknown)))
+        ));
+        // No more APDUs are sent once the procedure has failed.
+        assert!(export.next().is_none());
+    });
+}
+
+#[test]
+fn import() {
+    let entries = [[1; 160], [2; 160]];
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x09, 1, 0, &[0, 0, 0, 2]), &[], OK)
+        .expect(&apdu(0x0a, 0, 0, &entries[0]), &[], OK)
+        .expect(&apdu(0x0a, 0, 0, &entries[1]), &[], 0x9d60);
+    run(mock, |c| {
+        let mut import = c.import(true, 2).unwrap();
+        import.send(&entries[0]).unwrap();
+        assert_eq!(import.remaining(), 1);
+        assert!(matches!(
+            import.send(&entries[1]),
+            Err(Error::Status(Status::DecryptFailed))
+        ));
+        assert_eq!(import.remaining(), 0);
+    });
+}
+
+#[test]
+#[should_panic(expected = "all entries have been imported")]
+fn import_too_many() {
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x09, 0, 0, &[0, 0, 0, 0]), &[], OK);
+    let mut client = Client::new(&mut mock);
+    let mut import = client.import(false, 0).unwrap();
+    let _ = import.send(&[0; 128]);
+}
+
+#[test]
+fn unexpected_command() {
+    let mut mock = Mock::new();
+    mock.expect(&apdu(0x0b, 0, 0, &[]), &[], OK);
+    let mut client = Client::new(&mut mock);
+    // The mock rejects the Quit APDU, as a device which disconnects would.
+    assert!(matches!(client.quit(), Ok(())));
+    assert!(matches!(client.clear(), Err(Error::Io(_))));
+}
+
+#[test]
+fn data_too_long() {
+    let mut mock = Mock::new();
+    let mut client = Client::new(&mut mock);
+    // Nothing is sent, as the length does not fit in the APDU header.
+    assert!(matches!(
+        client.command(Instruction::Add, 0, 0, &[0; 256]),
+        Err(Error::DataTooLong(256))
+    ));
+}
@@ -0,0 +1,157 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test against the application running in a freshly started Speculos
+//! emulator, whose APDU port address is set in the `SPECULOS` environment
+//! variable, for instance:
+//!
+//! ```text
+//! speculos.py --display headless ../target/thumbv6m-none-eabi/release/nanopass &
+//! SPECULOS=127.0.0.1:9999 cargo test -p nanopass-client --test speculos -- --ignored
+//! ```
+//!
+//! Buttons are pressed through the REST API of the emulator, on port 5000 of
+//! the same host unless `SPECULOS_API` is set. The test is ignored unless
+//! requested, as it needs the emulator.
+
+use nanopass_client::client::{Credentials, Password};
+use nanopass_client::transport::Speculos;
+use nanopass_client::{Client, Error, Protection, Status};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+const NOW: u32 = 1700000000;
+
+/// Presses the buttons of the emulator while `f` sends a command, as the
+/// automaton of the Python tests does: `l`, `r` and `b` stand for the left,
+/// right and both buttons.
+fn with_buttons<R>(api: &str, buttons: &'static str, f: impl FnOnce() -> R) -> R {
+    let api = api.to_string();
+    let presses = thread::spawn(move || {
+        for button in buttons.chars() {
+            // Leave time to the application to process the previous event.
+            thread::sleep(Duration::from_millis(300));
+            let name = match button {
+                'l' => "left",
+                'r' => "right",
+                _ => "both",
+            };
+            let body = r#"{"action": "press-and-release"}"#;
+            let mut stream = TcpStream::connect(&api).unwrap();
+            write!(
+                stream,
+                "POST /button/{} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                name,
+                api,
+                body.len(),
+                body
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        }
+    });
+    let result = f();
+    presses.join().unwrap();
+    result
+}
+
+#[test]
+#[ignore = "needs a Speculos emulator, whose address is set in SPECULOS"]
+fn emulator() {
+    let address = std::env::var("SPECULOS").expect("SPECULOS is not set");
+    let api = std::env::var("SPECULOS_API").unwrap_or_else(|_| {
+        let host = address
+            .rsplit_once(':')
+            .map_or(address.as_str(), |(h, _)| h);
+        format!("{}:5000", host)
+    });
+    let mut client = Client::new(Speculos::connect(&address).unwrap());
+    let entries = [("gitlab", "alice", "epuu7Aeja9"), ("vpn", "", "oaw5Ohhe")];
+
+    assert!(client.version().unwrap().starts_with("1."));
+    assert_eq!(client.size(0).unwrap(), 0);
+    client.set_time(NOW).unwrap();
+    for (name, login, password) in entries {
+        with_buttons(&api, "rb", || {
+            client.add(
+                name,
+                login,
+                Password::Supplied(password),
+                Protection::Confirm,
+                0x01,
+            )
+        })
+        .unwrap();
+    }
+    assert_eq!(client.names(0x01).unwrap(), ["gitlab", "vpn"]);
+    assert!(client.names(0x02).unwrap().is_empty());
+    assert!(client.has_name("vpn").unwrap());
+    assert!(!client.has_name("undefined").unwrap());
+    assert!(matches!(
+        client.metadata("undefined"),
+        Err(Error::Status(Status::EntryNotFound))
+    ));
+    assert!(matches!(
+        client.rename("gitlab", "vpn"),
+        Err(Error::Status(Status::NameExists))
+    ));
+    let credentials = with_buttons(&api, "rb", || client.get("gitlab")).unwrap();
+    assert_eq!(
+        credentials,
+        Credentials {
+            login: "alice".into(),
+            password: "epuu7Aeja9".into()
+        }
+    );
+    let metadata = client.metadata("gitlab").unwrap();
+    assert_eq!(metadata.accesses, 1);
+    assert_eq!(metadata.created, NOW - NOW % 86400);
+    let stats = client.storage_stats().unwrap();
+    assert!(stats.used >= 2 && stats.used + stats.free == stats.capacity);
+
+    // Export, then import the entries back in the emptied vault
+    let plaintext: Vec<_> = with_buttons(&api, "brb", || client.export(false, 0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(plaintext.len(), 2);
+    assert!(plaintext.iter().all(|e| e.len() == 128));
+    assert_eq!(&plaintext[0][..7], b"gitlab\0");
+    let encrypted: Vec<_> = with_buttons(&api, "b", || client.export(true, 0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert!(encrypted.iter().all(|e| e.len() == 16 + 128 + 16));
+    for (name, _, _) in entries {
+        with_buttons(&api, "rb", || client.delete(name)).unwrap();
+    }
+    assert_eq!(client.size(0).unwrap(), 0);
+
+    let mut import = with_buttons(&api, "b", || client.import(true, 2)).unwrap();
+    for entry in &encrypted {
+        import.send(entry).unwrap();
+    }
+    assert_eq!(client.names(0).unwrap(), ["gitlab", "vpn"]);
+    let credentials = with_buttons(&api, "rb", || client.get("vpn")).unwrap();
+    assert_eq!(credentials.password, "oaw5Ohhe");
+
+    for (name, _, _) in entries {
+        with_buttons(&api, "rb", || client.delete(name)).unwrap();
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Import of entries into the application, running on a device or on the
+//! Speculos emulator.
+
+use crate::entry::Entry;
+use crate::Error;
+use nanopass_client::transport::Transport;
+use nanopass_client::Client;
+
+/// Oldest version of the application accepting attributes during import.
+const MIN_IMPORT_VERSION: [u32; 3] = [1, 3, 0];
+
+/// Imports entries in plaintext. The user is asked to confirm the import on
+/// the device first. Entries with the name of a stored entry replace it.
+///
+/// # Arguments
+///
+/// * `entries` - Checked entries.
+/// * `progress` - Called with the number of entries imported so far.
+pub fn import<T: Transport>(
+    client: &mut Client<T>,
+    entries: &[Entry],
+    mut progress: impl FnMut(usize),
+) -> Result<(), Error> {
+    let version = client.version()?;
+    let parsed: Vec<u32> = version.split('.').filter_map(|n| n.parse().ok()).collect();
+    if parsed.as_slice() < MIN_IMPORT_VERSION.as_slice() {
+        return Err(Error::Format(format!(
+            "application version {} is too old, 1.3.0 or later is required",
+            version
+        )));
+    }
+    let mut import = client.import(false, entries.len() as u32)?;
+    for (i, entry) in entries.iter().enumerate() {
+        import.send(&entry.to_bytes())?;
+        progress(i + 1);
+    }
+    Ok(())
+}
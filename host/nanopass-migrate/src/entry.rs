@@ -0,0 +1,287 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Password entries, as exchanged with the application during import and
+//! export.
+
+use crate::Error;
+use std::collections::HashSet;
+use std::fmt;
+
+pub use nanopass_client::{ATTRIBUTES_LEN, FIELD_LEN, TAGS};
+
+/// Length of a plaintext entry: name, login, password and attributes.
+pub const ENTRY_LEN: usize = 3 * FIELD_LEN + ATTRIBUTES_LEN;
+
+const DAY: u32 = 86400;
+
+/// A password entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub login: String,
+    pub password: String,
+    /// Protection level, see the implementation documentation.
+    pub protection: u8,
+    /// Tags mask.
+    pub tags: u8,
+    /// Creation time, in seconds since the Unix epoch, or 0 if unknown.
+    pub created: u32,
+    /// Last modification time, in seconds since the Unix epoch, or 0 if
+    /// unknown.
+    pub updated: u32,
+    /// Number of times the password has been read.
+    pub accesses: u32,
+}
+
+/// Field of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Name,
+    Login,
+    Password,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Field::Name => "name",
+            Field::Login => "login",
+            Field::Password => "password",
+        })
+    }
+}
+
+/// Reason why an entry cannot be stored by the application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The entry has no name.
+    EmptyName,
+    /// A field is longer than `FIELD_LEN` bytes.
+    TooLong(Field, usize),
+    /// A field contains a null byte, which would end it early.
+    NullByte(Field),
+    /// Another entry has the same name. The application identifies entries by
+    /// name, so the second one would replace the first.
+    DuplicateName,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::EmptyName => write!(f, "no name"),
+            Problem::TooLong(field, len) => {
+                write!(
+                    f,
+                    "{} is {} bytes long, at most {} fit",
+                    field, len, FIELD_LEN
+                )
+            }
+            Problem::NullByte(field) => write!(f, "{} contains a null byte", field),
+            Problem::DuplicateName => write!(f, "another entry has the same name"),
+        }
+    }
+}
+
+impl Entry {
+    fn fields(&self) -> [(Field, &str); 3] {
+        [
+            (Field::Name, &self.name),
+            (Field::Login, &self.login),
+            (Field::Password, &self.password),
+        ]
+    }
+
+    /// Checks that the entry fits in the fields of the application.
+    pub fn check(&self) -> Result<(), Problem> {
+        if self.name.is_empty() {
+            return Err(Problem::EmptyName);
+        }
+        for (field, value) in self.fields() {
+            if value.len() > FIELD_LEN {
+                return Err(Problem::TooLong(field, value.len()));
+            }
+            if value.contains('\0') {
+                return Err(Problem::NullByte(field));
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes an entry returned by the ExportNext APDU in plaintext.
+    /// Entries exported by versions prior to 1.3.0 have no attributes block,
+    /// and those exported by versions prior to 1.1.0 have no login either.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Entry, Error> {
+        let field = |i: usize| {
+            let block = &bytes[i * FIELD_LEN..(i + 1) * FIELD_LEN];
+            let len = block.iter().position(|&b| b == 0).unwrap_or(FIELD_LEN);
+            String::from_utf8(block[..len].to_vec())
+                .map_err(|_| Error::Format("entry field is not valid UTF-8".into()))
+        };
+        let u32_at =
+            |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        match bytes.len() {
+            len if len == 2 * FIELD_LEN => Ok(Entry {
+                name: field(0)?,
+                password: field(1)?,
+                ..Default::default()
+            }),
+            len if len == 3 * FIELD_LEN || len == ENTRY_LEN => {
+                let mut entry = Entry {
+                    name: field(0)?,
+                    login: field(1)?,
+                    password: field(2)?,
+                    ..Default::default()
+                };
+                if len == ENTRY_LEN {
+                    let attributes = 3 * FIELD_LEN;
+                    entry.protection = bytes[attributes];
+                    entry.tags = bytes[attributes + 1];
+                    entry.created = u32_at(attributes + 4);
+                    entry.updated = u32_at(attributes + 8);
+                    entry.accesses = u32_at(attributes + 12);
+                }
+                Ok(entry)
+            }
+            len => Err(Error::Format(format!("invalid entry length {}", len))),
+        }
+    }
+
+    /// Index in `TAGS` of the first tag of the entry, if any. Formats with
+    /// folders instead of tags keep this one only.
+    pub fn first_tag(&self) -> Option<usize> {
+        (0..TAGS.len()).find(|i| self.tags & (1 << i) != 0)
+    }
+
+    /// Encodes the entry as expected by the ImportNext APDU: name, login and
+    /// password padded with zeros, followed by the attributes block.
+    ///
+    /// The entry must have been checked first.
+    pub fn to_bytes(&self) -> [u8; ENTRY_LEN] {
+        let mut bytes = [0; ENTRY_LEN];
+        for (i, (_, value)) in self.fields().iter().enumerate() {
+            bytes[i * FIELD_LEN..i * FIELD_LEN + value.len()].copy_from_slice(value.as_bytes());
+        }
+        let attributes = &mut bytes[3 * FIELD_LEN..];
+        attributes[0] = self.protection;
+        attributes[1] = self.tags;
+        attributes[4..8].copy_from_slice(&self.created.to_be_bytes());
+        attributes[8..12].copy_from_slice(&self.updated.to_be_bytes());
+        attributes[12..16].copy_from_slice(&self.accesses.to_be_bytes());
+        bytes
+    }
+}
+
+/// An entry which cannot be imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    /// Position of the entry among the logins read, starting at 1.
+    pub position: usize,
+    pub name: String,
+    pub problem: Problem,
+}
+
+/// Splits entries between those which can be imported and those which cannot.
+/// Of entries sharing a name, the first one is kept.
+pub fn check_all(entries: Vec<Entry>) -> (Vec<Entry>, Vec<Rejected>) {
+    let mut names = HashSet::new();
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+    for (i, entry) in entries.into_iter().enumerate() {
+        let result = entry
+            .check()
+            .and_then(|()| match names.insert(entry.name.clone()) {
+                true => Ok(()),
+                false => Err(Problem::DuplicateName),
+            });
+        match result {
+            Ok(()) => accepted.push(entry),
+            Err(problem) => rejected.push(Rejected {
+                position: i + 1,
+                name: entry.name,
+                problem,
+            }),
+        }
+    }
+    (accepted, rejected)
+}
+
+/// Tags mask matching labels such as folder or group names. Labels which are
+/// not tag names, ignoring case, are ignored.
+pub fn tags_from_labels<'a>(labels: impl IntoIterator<Item = &'a str>) -> u8 {
+    labels
+        .into_iter()
+        .filter_map(|label| {
+            TAGS.iter()
+                .position(|tag| tag.eq_ignore_ascii_case(label.trim()))
+        })
+        .fold(0, |mask, i| mask | (1 << i))
+}
+
+/// Parses the date of an ISO 8601 timestamp, such as `2021-03-14T15:09:26Z`.
+/// Returns the time of the start of that day, in seconds since the Unix epoch,
+/// as the application only keeps days.
+pub fn parse_date(timestamp: &str) -> Option<u32> {
+    let date = timestamp.get(..10)?;
+    let mut parts = date.split('-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days from civil, after Howard Hinnant's algorithm
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    u32::try_from(days * DAY as i64).ok()
+}
+
+/// Formats a time as an ISO 8601 timestamp, such as
+/// `2021-03-14T15:09:26.000Z`.
+///
+/// # Arguments
+///
+/// * `time` - Seconds since the Unix epoch.
+pub fn format_date(time: u32) -> String {
+    // Civil from days, after Howard Hinnant's algorithm
+    let days = (time / DAY) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    let seconds = time % DAY;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.000Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Host name of a URL, used to name entries which have none.
+pub fn host_name(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    host.split(':').next().unwrap_or("")
+}
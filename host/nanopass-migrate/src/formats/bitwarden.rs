@@ -0,0 +1,143 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bitwarden JSON export.
+//!
+//! Only login items are imported. The folder of an item is mapped to the tag
+//! of the same name, if any, and the first tag of an entry becomes its folder
+//! on export.
+
+use super::Parsed;
+use crate::entry::{self, Entry};
+use crate::Error;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Item type of logins. Other types are secure notes, cards and identities.
+const TYPE_LOGIN: u8 = 1;
+
+#[derive(Deserialize)]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    #[serde(rename = "type")]
+    kind: u8,
+    name: String,
+    folder_id: Option<String>,
+    login: Option<Login>,
+    creation_date: Option<String>,
+    revision_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// Reads the entries of a Bitwarden JSON export.
+pub fn parse(data: &str) -> Result<Parsed, Error> {
+    let export: Export = serde_json::from_str(data)?;
+    if export.encrypted {
+        return Err(Error::Format(
+            "encrypted Bitwarden exports are not supported, export in JSON \
+             without encryption"
+                .into(),
+        ));
+    }
+    let mut parsed = Parsed::default();
+    for item in export.items {
+        let login = match (item.kind, item.login) {
+            (TYPE_LOGIN, Some(login)) => login,
+            _ => {
+                parsed.skipped.push(item.name);
+                continue;
+            }
+        };
+        let folder = export
+            .folders
+            .iter()
+            .find(|f| Some(&f.id) == item.folder_id.as_ref())
+            .map(|f| f.name.as_str());
+        let date = |d: &Option<String>| d.as_deref().and_then(entry::parse_date).unwrap_or(0);
+        parsed.entries.push(Entry {
+            name: item.name,
+            login: login.username.unwrap_or_default(),
+            password: login.password.unwrap_or_default(),
+            tags: entry::tags_from_labels(folder.into_iter().flat_map(|f| f.split('/'))),
+            created: date(&item.creation_date),
+            updated: date(&item.revision_date),
+            ..Default::default()
+        });
+    }
+    Ok(parsed)
+}
+
+/// Identifier of an item or folder. Bitwarden only uses them to link items to
+/// their folders during import, and assigns new ones.
+fn uuid(kind: u32, index: usize) -> String {
+    format!("00000000-0000-4000-8000-{:04x}{:08x}", kind, index)
+}
+
+/// Writes entries as a Bitwarden JSON export. The first tag of each entry
+/// becomes its folder.
+pub fn write(entries: &[Entry]) -> String {
+    let folders: Vec<_> = entry::TAGS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| entries.iter().any(|e| e.first_tag() == Some(*i)))
+        .map(|(i, tag)| json!({ "id": uuid(1, i), "name": tag }))
+        .collect();
+    let items: Vec<_> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+            json!({
+                "id": uuid(2, i),
+                "folderId": e.first_tag().map(|tag| uuid(1, tag)),
+                "type": TYPE_LOGIN,
+                "reprompt": 0,
+                "name": e.name,
+                "notes": null,
+                "favorite": false,
+                "login": {
+                    "uris": [],
+                    "username": e.login,
+                    "password": e.password,
+                    "totp": null,
+                },
+                "collectionIds": null,
+                "creationDate": entry::format_date(e.created),
+                "revisionDate": entry::format_date(e.updated),
+            })
+        })
+        .collect();
+    let export = json!({ "encrypted": false, "folders": folders, "items": items });
+    serde_json::to_string_pretty(&export).unwrap()
+}
@@ -0,0 +1,142 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CSV exports with a header row.
+//!
+//! Columns are found by name, ignoring case, so that the exports of most
+//! password managers and web browsers are understood without configuration:
+//!
+//! | Source    | Name      | Login          | Password         | URL         |
+//! |-----------|-----------|----------------|------------------|-------------|
+//! | KeePassXC | Title     | Username       | Password         | URL         |
+//! | KeePass 2 | Account   | Login Name     | Password         | Web Site    |
+//! | 1Password | Title     | Username       | Password         | Url         |
+//! | Bitwarden | name      | login_username | login_password   | login_uri   |
+//! | Browsers  | name      | username       | password         | url         |
+//!
+//! Entries with no name are named after the host of their URL. Group, folder
+//! and tags columns are mapped to the tags of the same name, if any. The
+//! creation and modification dates of KeePassXC exports are kept.
+
+use super::Parsed;
+use crate::entry::{self, Entry};
+use crate::Error;
+
+const NAME_COLUMNS: [&str; 3] = ["title", "name", "account"];
+const LOGIN_COLUMNS: [&str; 4] = ["username", "login_username", "login name", "login"];
+const PASSWORD_COLUMNS: [&str; 2] = ["password", "login_password"];
+const URL_COLUMNS: [&str; 4] = ["url", "login_uri", "web site", "website"];
+const LABEL_COLUMNS: [&str; 3] = ["group", "folder", "tags"];
+const CREATED_COLUMNS: [&str; 1] = ["created"];
+const UPDATED_COLUMNS: [&str; 1] = ["last modified"];
+
+/// Bitwarden CSV exports list all items, logins having this type.
+const BITWARDEN_LOGIN: &str = "login";
+
+/// Reads the entries of a CSV export.
+pub fn parse(data: &str) -> Result<Parsed, Error> {
+    let mut reader = ::csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(data.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|h| h.trim().to_ascii_lowercase())
+        .collect();
+    let find = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|n| headers.iter().position(|h| h == n))
+    };
+    let password = find(&PASSWORD_COLUMNS)
+        .ok_or_else(|| Error::Format("no password column in the CSV header".into()))?;
+    let (name, login, url) = (
+        find(&NAME_COLUMNS),
+        find(&LOGIN_COLUMNS),
+        find(&URL_COLUMNS),
+    );
+    let (created, updated) = (find(&CREATED_COLUMNS), find(&UPDATED_COLUMNS));
+    let kind = find(&["type"]);
+    let labels: Vec<usize> = LABEL_COLUMNS.iter().filter_map(|n| find(&[n])).collect();
+
+    let mut parsed = Parsed::default();
+    for record in reader.records() {
+        let record = record?;
+        let get = |column: Option<usize>| column.and_then(|c| record.get(c)).unwrap_or("");
+        let mut entry = Entry {
+            name: get(name).to_string(),
+            login: get(login).to_string(),
+            password: get(Some(password)).to_string(),
+            tags: entry::tags_from_labels(
+                labels
+                    .iter()
+                    .flat_map(|&c| get(Some(c)).split(['/', ',', ';'])),
+            ),
+            created: entry::parse_date(get(created)).unwrap_or(0),
+            updated: entry::parse_date(get(updated)).unwrap_or(0),
+            ..Default::default()
+        };
+        if entry.name.is_empty() {
+            entry.name = entry::host_name(get(url)).to_string();
+        }
+        match get(kind) {
+            "" | BITWARDEN_LOGIN => parsed.entries.push(entry),
+            _ => parsed.skipped.push(entry.name),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Writes entries as CSV, with the columns of KeePassXC exports, which most
+/// password managers can import. The first tag of each entry becomes its group.
+pub fn write(entries: &[Entry]) -> String {
+    let mut writer = ::csv::WriterBuilder::new()
+        .quote_style(::csv::QuoteStyle::Always)
+        .from_writer(Vec::new());
+    let header = [
+        "Group",
+        "Title",
+        "Username",
+        "Password",
+        "URL",
+        "Notes",
+        "TOTP",
+        "Icon",
+        "Last Modified",
+        "Created",
+    ];
+    // Writing to memory cannot fail.
+    writer.write_record(header).unwrap();
+    for e in entries {
+        let group = match e.first_tag() {
+            Some(tag) => format!("Root/{}", entry::TAGS[tag]),
+            None => "Root".into(),
+        };
+        writer
+            .write_record([
+                group.as_str(),
+                &e.name,
+                &e.login,
+                &e.password,
+                "",
+                "",
+                "",
+                "0",
+                &entry::format_date(e.updated),
+                &entry::format_date(e.created),
+            ])
+            .unwrap();
+    }
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
@@ -0,0 +1,125 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! KeePass 2 XML export.
+//!
+//! Entries of the recycle bin and previous versions of entries are not
+//! imported. Groups containing an entry and tags of the entry are mapped to the
+//! tags of the same name, if any.
+
+use super::Parsed;
+use crate::entry::{self, Entry};
+use crate::Error;
+use roxmltree::{Document, Node};
+
+/// Returns the text of the first child element with the given name.
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .and_then(|n| n.text())
+}
+
+/// Reads the fields of an entry. Fields are stored as key and value pairs,
+/// the standard keys being Title, UserName, Password, URL and Notes.
+fn parse_entry(node: Node, labels: &[&str]) -> Entry {
+    let field = |key: &str| {
+        node.children()
+            .filter(|n| n.has_tag_name("String"))
+            .find(|n| child_text(*n, "Key") == Some(key))
+            .and_then(|n| child_text(n, "Value"))
+            .unwrap_or("")
+            .to_string()
+    };
+    let times = node.children().find(|n| n.has_tag_name("Times"));
+    let date = |name: &str| {
+        times
+            .and_then(|t| child_text(t, name))
+            .and_then(entry::parse_date)
+            .unwrap_or(0)
+    };
+    let tags = child_text(node, "Tags").unwrap_or("");
+    let mut name = field("Title");
+    if name.is_empty() {
+        name = entry::host_name(&field("URL")).to_string();
+    }
+    Entry {
+        name,
+        login: field("UserName"),
+        password: field("Password"),
+        tags: entry::tags_from_labels(labels.iter().copied().chain(tags.split([';', ',']))),
+        created: date("CreationTime"),
+        updated: date("LastModificationTime"),
+        ..Default::default()
+    }
+}
+
+/// Reads the entries of a group and of its subgroups.
+///
+/// # Arguments
+///
+/// * `labels` - Names of the parent groups.
+/// * `trash` - UUID of the recycle bin group.
+fn parse_group<'a>(
+    group: Node<'a, '_>,
+    labels: &mut Vec<&'a str>,
+    trash: Option<&str>,
+    parsed: &mut Parsed,
+) {
+    let in_trash = trash.is_some() && child_text(group, "UUID") == trash;
+    labels.push(child_text(group, "Name").unwrap_or(""));
+    for node in group.children() {
+        if node.has_tag_name("Entry") {
+            let entry = parse_entry(node, labels);
+            match in_trash {
+                true => parsed.skipped.push(entry.name),
+                false => parsed.entries.push(entry),
+            }
+        } else if node.has_tag_name("Group") {
+            match in_trash {
+                true => {
+                    let mut trashed = Parsed::default();
+                    parse_group(node, labels, None, &mut trashed);
+                    parsed
+                        .skipped
+                        .extend(trashed.entries.into_iter().map(|e| e.name));
+                    parsed.skipped.extend(trashed.skipped);
+                }
+                false => parse_group(node, labels, trash, parsed),
+            }
+        }
+    }
+    labels.pop();
+}
+
+/// Reads the entries of a KeePass 2 XML export.
+pub fn parse(data: &str) -> Result<Parsed, Error> {
+    let document = Document::parse(data)?;
+    let file = document.root_element();
+    if !file.has_tag_name("KeePassFile") {
+        return Err(Error::Format("not a KeePass XML file".into()));
+    }
+    let trash = file
+        .children()
+        .find(|n| n.has_tag_name("Meta"))
+        .and_then(|meta| child_text(meta, "RecycleBinUUID"));
+    let root = file
+        .children()
+        .find(|n| n.has_tag_name("Root"))
+        .ok_or_else(|| Error::Format("no Root element".into()))?;
+    let mut parsed = Parsed::default();
+    for group in root.children().filter(|n| n.has_tag_name("Group")) {
+        parse_group(group, &mut Vec::new(), trash, &mut parsed);
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,143 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export formats of other password managers, and of the application.
+
+pub mod bitwarden;
+pub mod csv;
+pub mod keepass;
+pub mod nanopass;
+pub mod pass;
+
+use crate::entry::Entry;
+use crate::Error;
+use std::path::Path;
+
+/// Entries read from an export file.
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub entries: Vec<Entry>,
+    /// Names of the items which are not logins, such as notes or cards, or
+    /// which are in the trash.
+    pub skipped: Vec<String>,
+}
+
+/// Supported export formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Bitwarden unencrypted JSON export.
+    Bitwarden,
+    /// KeePass 2 XML export, also written by KeePassXC.
+    Keepass,
+    /// CSV with a header row, as written by KeePass, KeePassXC, 1Password,
+    /// Bitwarden or web browsers.
+    Csv,
+    /// Directory of a pass(1) password store.
+    Pass,
+}
+
+impl Format {
+    /// Guesses the format of a file from its extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        if path.is_dir() {
+            return Some(Format::Pass);
+        }
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(Format::Bitwarden),
+            "xml" => Some(Format::Keepass),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    /// Reads the entries of an export file.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Content of the file.
+    /// * `now` - Current time, in seconds since the Unix epoch, used for
+    ///   entries with no creation or modification time.
+    pub fn parse(self, data: &str, now: u32) -> Result<Parsed, Error> {
+        let mut parsed = match self {
+            Format::Bitwarden => bitwarden::parse(data)?,
+            Format::Keepass => keepass::parse(data)?,
+            Format::Csv => csv::parse(data)?,
+            Format::Pass => return Err(Error::Format("a password store is a directory".into())),
+        };
+        set_missing_times(&mut parsed, now);
+        Ok(parsed)
+    }
+
+    /// Reads the entries of an export file, or of a password store.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Export file, or root directory of the password store.
+    /// * `now` - Current time, in seconds since the Unix epoch, used for
+    ///   entries with no creation or modification time.
+    pub fn read(self, path: &Path, now: u32) -> Result<Parsed, Error> {
+        match self {
+            Format::Pass => {
+                let mut parsed = pass::read_store(path, &pass::Gpg::new())?;
+                set_missing_times(&mut parsed, now);
+                Ok(parsed)
+            }
+            _ => self.parse(&std::fs::read_to_string(path)?, now),
+        }
+    }
+}
+
+/// Formats entries can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Target {
+    /// Bitwarden unencrypted JSON export.
+    Bitwarden,
+    /// CSV with the columns of KeePassXC, which KeePass and most password
+    /// managers can import.
+    Csv,
+    /// Directory of a pass(1) password store, initialized with `pass init`.
+    Pass,
+}
+
+impl Target {
+    /// Writes entries to a file, or to a password store.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Output file, or root directory of the password store.
+    pub fn write(self, path: &Path, entries: &[Entry]) -> Result<(), Error> {
+        match self {
+            Target::Bitwarden => std::fs::write(path, bitwarden::write(entries))?,
+            Target::Csv => std::fs::write(path, csv::write(entries))?,
+            Target::Pass => pass::write_store(path, entries, &pass::Gpg::new())?,
+        }
+        Ok(())
+    }
+}
+
+/// Sets the creation and modification times of entries which have none.
+fn set_missing_times(parsed: &mut Parsed, now: u32) {
+    for entry in parsed.entries.iter_mut() {
+        if entry.created == 0 {
+            entry.created = match entry.updated {
+                0 => now,
+                updated => updated,
+            };
+        }
+        if entry.updated == 0 {
+            entry.updated = entry.created;
+        }
+    }
+}
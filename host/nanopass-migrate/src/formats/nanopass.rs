@@ -0,0 +1,61 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export files written by `nanopass.py export`: the version of the
+//! application, whether entries are encrypted, and the entries returned by the
+//! ExportNext APDU, in hexadecimal.
+
+use super::Parsed;
+use crate::entry::Entry;
+use crate::Error;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Export {
+    encrypted: bool,
+    entries: Vec<String>,
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digits = std::str::from_utf8(pair).ok().filter(|d| d.len() == 2);
+            digits
+                .and_then(|d| u8::from_str_radix(d, 16).ok())
+                .ok_or_else(|| Error::Format("invalid hexadecimal entry".into()))
+        })
+        .collect()
+}
+
+/// Reads the entries of a plaintext export.
+pub fn parse(data: &str) -> Result<Parsed, Error> {
+    let export: Export = serde_json::from_str(data)?;
+    if export.encrypted {
+        return Err(Error::Format(
+            "encrypted exports can only be read by the application, export \
+             with --no-encrypt"
+                .into(),
+        ));
+    }
+    let entries = export
+        .entries
+        .iter()
+        .map(|hex| Entry::from_bytes(&from_hex(hex)?))
+        .collect::<Result<_, _>>()?;
+    Ok(Parsed {
+        entries,
+        skipped: Vec::new(),
+    })
+}
@@ -0,0 +1,230 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Password store of pass(1), the standard Unix password manager.
+//!
+//! Each entry is a file encrypted with GnuPG, holding the password on its
+//! first line, followed by a `login:` line. Files are named after their entry,
+//! slashes being replaced by underscores, and are stored in a directory named
+//! after the first tag of the entry, if any. They are encrypted for the keys
+//! listed in the `.gpg-id` file at the root of the store, written by
+//! `pass init`.
+//!
+//! When reading a store, directories named after a tag are mapped to that tag,
+//! and the modification time of each file is used as the modification time of
+//! its entry.
+
+use super::Parsed;
+use crate::entry::{self, Entry};
+use crate::Error;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::UNIX_EPOCH;
+
+/// File listing the keys the store is encrypted for.
+const GPG_ID: &str = ".gpg-id";
+const EXTENSION: &str = "gpg";
+const LOGIN_PREFIX: &str = "login:";
+
+/// Runs GnuPG, as pass does.
+#[derive(Debug, Default)]
+pub struct Gpg {
+    home: Option<PathBuf>,
+}
+
+impl Gpg {
+    /// Uses the keyring of the user.
+    pub fn new() -> Gpg {
+        Gpg::default()
+    }
+
+    /// Uses the keyring of the given GnuPG home directory.
+    pub fn with_home(home: &Path) -> Gpg {
+        Gpg {
+            home: Some(home.to_path_buf()),
+        }
+    }
+
+    fn run(&self, args: &[&std::ffi::OsStr], input: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut command = Command::new("gpg");
+        command.args(["--quiet", "--batch", "--yes", "--compress-algo=none"]);
+        if let Some(home) = &self.home {
+            command.env("GNUPGHOME", home);
+        }
+        let mut child = command
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Gpg(format!("cannot run gpg: {}", e)))?;
+        child.stdin.take().unwrap().write_all(input)?;
+        let output = child.wait_with_output()?;
+        match output.status.success() {
+            true => Ok(output.stdout),
+            false => Err(Error::Gpg(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            )),
+        }
+    }
+
+    /// Encrypts data to a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `recipients` - Identifiers of the keys to encrypt for.
+    pub fn encrypt(&self, recipients: &[String], data: &[u8], output: &Path) -> Result<(), Error> {
+        let mut args = vec!["--no-encrypt-to".as_ref(), "--encrypt".as_ref()];
+        for recipient in recipients {
+            args.push("--recipient".as_ref());
+            args.push(recipient.as_ref());
+        }
+        args.push("--output".as_ref());
+        args.push(output.as_os_str());
+        self.run(&args, data).map(|_| ())
+    }
+
+    /// Decrypts a file.
+    pub fn decrypt(&self, input: &Path) -> Result<Vec<u8>, Error> {
+        self.run(&["--decrypt".as_ref(), input.as_os_str()], &[])
+    }
+}
+
+/// Path of the file of an entry, relative to the root of the store.
+pub fn entry_path(entry: &Entry) -> PathBuf {
+    let mut name = entry.name.replace('/', "_");
+    // Hidden files are not entries.
+    if name.starts_with('.') {
+        name.replace_range(..1, "_");
+    }
+    let mut path = PathBuf::new();
+    if let Some(tag) = entry.first_tag() {
+        path.push(entry::TAGS[tag]);
+    }
+    path.push(format!("{}.{}", name, EXTENSION));
+    path
+}
+
+/// Content of the file of an entry, before encryption.
+pub fn entry_content(entry: &Entry) -> String {
+    let mut content = format!("{}\n", entry.password);
+    if !entry.login.is_empty() {
+        content.push_str(&format!("{} {}\n", LOGIN_PREFIX, entry.login));
+    }
+    content
+}
+
+/// Reads an entry from the decrypted content of its file.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file, relative to the root of the store.
+pub fn parse_entry(path: &Path, content: &str) -> Entry {
+    let mut lines = content.lines();
+    let password = lines.next().unwrap_or("").to_string();
+    let login = lines
+        .find_map(|line| line.strip_prefix(LOGIN_PREFIX))
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    let folders = path.parent().into_iter().flat_map(|p| p.iter());
+    Entry {
+        name: path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        login,
+        password,
+        tags: entry::tags_from_labels(folders.filter_map(|f| f.to_str())),
+        ..Default::default()
+    }
+}
+
+/// Writes entries to a store initialized with `pass init`. Nothing is written
+/// if an entry is already in the store.
+pub fn write_store(root: &Path, entries: &[Entry], gpg: &Gpg) -> Result<(), Error> {
+    let recipients: Vec<String> = std::fs::read_to_string(root.join(GPG_ID))
+        .map_err(|_| {
+            Error::Format(format!(
+                "no {} file in {}, initialize the store with `pass init`",
+                GPG_ID,
+                root.display()
+            ))
+        })?
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let mut paths = HashSet::new();
+    for entry in entries {
+        let path = entry_path(entry);
+        if root.join(&path).exists() || !paths.insert(path.clone()) {
+            return Err(Error::Format(format!(
+                "{} is already in the store",
+                path.display()
+            )));
+        }
+    }
+    for entry in entries {
+        let path = root.join(entry_path(entry));
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        gpg.encrypt(&recipients, entry_content(entry).as_bytes(), &path)?;
+    }
+    Ok(())
+}
+
+/// Lists the files of the entries of a store, skipping hidden files such as
+/// `.gpg-id` or `.git`.
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for item in std::fs::read_dir(dir)? {
+        let path = item?.path();
+        if path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else if path.extension().is_some_and(|e| e == EXTENSION) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the entries of a store, decrypting them with GnuPG.
+pub fn read_store(root: &Path, gpg: &Gpg) -> Result<Parsed, Error> {
+    let mut files = Vec::new();
+    list_files(root, &mut files)?;
+    files.sort();
+    let mut parsed = Parsed::default();
+    for file in files {
+        let content = gpg.decrypt(&file)?;
+        let mut entry = parse_entry(
+            file.strip_prefix(root).unwrap(),
+            &String::from_utf8_lossy(&content),
+        );
+        entry.updated = std::fs::metadata(&file)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as u32);
+        parsed.entries.push(entry);
+    }
+    Ok(parsed)
+}
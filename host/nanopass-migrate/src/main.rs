@@ -0,0 +1,153 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{Parser, Subcommand};
+use nanopass_client::transport::{Speculos, Transport};
+use nanopass_client::Client;
+use nanopass_migrate::device;
+use nanopass_migrate::entry::{self, Entry};
+use nanopass_migrate::formats::{nanopass, Format, Target};
+use nanopass_migrate::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Parser)]
+#[command(about = "Migrate passwords between NanoPass and other password managers")]
+struct Cli {
+    /// Use the Speculos emulator listening for APDUs at this address, instead
+    /// of a device, for instance 127.0.0.1:9999.
+    #[arg(long, global = true)]
+    speculos: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import the passwords of another password manager into the device.
+    Import {
+        /// Export file of the other password manager.
+        path: PathBuf,
+        /// Format of the file. Guessed from its extension if not set.
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+        /// Only report the entries which would be imported or rejected.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Convert a plaintext export of the application, written by
+    /// `nanopass.py export --no-encrypt`, to the format of another password
+    /// manager.
+    Export {
+        /// Export file of the application.
+        input: PathBuf,
+        /// Output file, or root directory of the password store.
+        output: PathBuf,
+        /// Format to convert to.
+        #[arg(long, value_enum)]
+        to: Target,
+    },
+}
+
+fn connect(speculos: &Option<String>) -> Result<Box<dyn Transport>, Error> {
+    if let Some(address) = speculos {
+        return Ok(Box::new(Speculos::connect(address.as_str())?));
+    }
+    #[cfg(target_os = "linux")]
+    return Ok(Box::new(nanopass_client::transport::Hid::open()?));
+    #[cfg(not(target_os = "linux"))]
+    Err(Error::Format(
+        "only Linux devices are supported, use --speculos with the emulator".into(),
+    ))
+}
+
+fn import(
+    speculos: &Option<String>,
+    path: PathBuf,
+    format: Option<Format>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let format = format
+        .or_else(|| Format::from_path(&path))
+        .ok_or_else(|| Error::Format("unknown file format, set it with --format".into()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    let parsed = format.read(&path, now)?;
+    if !parsed.skipped.is_empty() {
+        println!(
+            "Skipped {} items which are not logins or are in the trash",
+            parsed.skipped.len()
+        );
+    }
+    let (entries, rejected) = entry::check_all(parsed.entries);
+    for r in rejected.iter() {
+        println!(
+            "Rejected entry {} \"{}\": {}",
+            r.position, r.name, r.problem
+        );
+    }
+    if dry_run {
+        for Entry { name, login, .. } in entries.iter() {
+            println!("Would import \"{}\" ({})", name, login);
+        }
+        println!("{} entries would be imported", entries.len());
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("Nothing to import");
+        return Ok(());
+    }
+    let mut client = Client::new(connect(speculos)?);
+    println!(
+        "Confirm the import of {} entries on the device...",
+        entries.len()
+    );
+    device::import(&mut client, &entries, |n| {
+        eprint!("\rImported {}/{}", n, entries.len())
+    })?;
+    eprintln!();
+    Ok(())
+}
+
+fn export(input: PathBuf, output: PathBuf, to: Target) -> Result<(), Error> {
+    let parsed = nanopass::parse(&std::fs::read_to_string(input)?)?;
+    to.write(&output, &parsed.entries)?;
+    println!(
+        "Converted {} entries. Protection levels and access counters are not \
+         kept, nor tags other than the first one of each entry.",
+        parsed.entries.len()
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Import {
+            path,
+            format,
+            dry_run,
+        } => import(&cli.speculos, path, format, dry_run),
+        Command::Export { input, output, to } => export(input, output, to),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nanopass_migrate::entry::{self, Entry};
+use nanopass_migrate::formats::{bitwarden, csv, nanopass, pass, Format};
+use nanopass_migrate::Error;
+use std::path::Path;
+use std::process::Command;
+
+const NOW: u32 = 1700000000;
+
+const TAG_WORK: u8 = 0x01;
+const TAG_PERSONAL: u8 = 0x02;
+const TAG_BANKING: u8 = 0x08;
+
+fn sample_entries() -> Vec<Entry> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/samples/nanopass.json");
+    nanopass::parse(&std::fs::read_to_string(path).unwrap())
+        .unwrap()
+        .entries
+}
+
+/// Entries as expected after a round trip through a format which keeps
+/// neither protection, access counters nor tags other than the first one.
+fn without_attributes(entries: &[Entry]) -> Vec<Entry> {
+    entries
+        .iter()
+        .map(|e| Entry {
+            protection: 0,
+            tags: e.first_tag().map_or(0, |tag| 1 << tag),
+            accesses: 0,
+            ..e.clone()
+        })
+        .collect()
+}
+
+#[test]
+fn nanopass_json() {
+    let entries = sample_entries();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(
+        entries[0],
+        Entry {
+            name: "gitlab".into(),
+            login: "alice".into(),
+            password: "epuu7Aeja9".into(),
+            protection: 0,
+            tags: TAG_WORK,
+            created: 1615680000,
+            updated: 1654041600,
+            accesses: 12,
+        }
+    );
+    assert_eq!(entries[1].protection, 1);
+    assert_eq!(entries[1].tags, TAG_BANKING | TAG_PERSONAL);
+    assert_eq!(entries[1].first_tag(), Some(1));
+    assert_eq!(entries[2].login, "");
+    assert_eq!(entries[2].first_tag(), None);
+}
+
+#[test]
+fn nanopass_encrypted() {
+    let data = r#"{"version": "1.4.0", "encrypted": true, "entries": []}"#;
+    assert!(matches!(nanopass::parse(data), Err(Error::Format(_))));
+    let data = r#"{"version": "1.4.0", "encrypted": false, "entries": ["6a6"]}"#;
+    assert!(matches!(nanopass::parse(data), Err(Error::Format(_))));
+}
+
+#[test]
+fn old_entries() {
+    let mut bytes = [0; 64];
+    bytes[..4].copy_from_slice(b"name");
+    bytes[32..36].copy_from_slice(b"pass");
+    let entry = Entry::from_bytes(&bytes).unwrap();
+    assert_eq!((entry.name.as_str(), entry.login.as_str()), ("name", ""));
+    assert_eq!(entry.password, "pass");
+
+    let mut bytes = [0; 96];
+    bytes[..4].copy_from_slice(b"name");
+    bytes[32..37].copy_from_slice(b"login");
+    bytes[64..68].copy_from_slice(b"pass");
+    let entry = Entry::from_bytes(&bytes).unwrap();
+    assert_eq!(entry.login, "login");
+    assert_eq!(entry.password, "pass");
+    assert_eq!(entry.tags, 0);
+
+    assert!(Entry::from_bytes(&[0; 100]).is_err());
+}
+
+#[test]
+fn entry_bytes() {
+    for entry in sample_entries() {
+        assert_eq!(Entry::from_bytes(&entry.to_bytes()).unwrap(), entry);
+    }
+}
+
+#[test]
+fn dates() {
+    assert_eq!(entry::format_date(0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(entry::format_date(951782400), "2000-02-29T00:00:00.000Z");
+    assert_eq!(entry::format_date(1615734566), "2021-03-14T15:09:26.000Z");
+    for time in [0, 951782400, 1654041600, u32::MAX - u32::MAX % 86400] {
+        assert_eq!(entry::parse_date(&entry::format_date(time)), Some(time));
+    }
+}
+
+#[test]
+fn bitwarden_round_trip() {
+    let entries = sample_entries();
+    let parsed = Format::Bitwarden
+        .parse(&bitwarden::write(&entries), NOW)
+        .unwrap();
+    assert!(parsed.skipped.is_empty());
+    assert_eq!(parsed.entries, without_attributes(&entries));
+}
+
+#[test]
+fn csv_round_trip() {
+    let entries = sample_entries();
+    let parsed = Format::Csv.parse(&csv::write(&entries), NOW).unwrap();
+    assert!(parsed.skipped.is_empty());
+    assert_eq!(parsed.entries, without_attributes(&entries));
+}
+
+#[test]
+fn pass_entries() {
+    let entries = sample_entries();
+    assert_eq!(pass::entry_path(&entries[0]), Path::new("work/gitlab.gpg"));
+    assert_eq!(
+        pass::entry_path(&entries[1]),
+        Path::new("personal/bank.example.com.gpg")
+    );
+    let hidden = Entry {
+        name: ".ssh/id".into(),
+        ..Default::default()
+    };
+    assert_eq!(pass::entry_path(&hidden), Path::new("_ssh_id.gpg"));
+
+    assert_eq!(
+        pass::entry_content(&entries[0]),
+        "epuu7Aeja9\nlogin: alice\n"
+    );
+    for e in entries {
+        let parsed = pass::parse_entry(&pass::entry_path(&e), &pass::entry_content(&e));
+        assert_eq!(parsed.name, e.name);
+        assert_eq!(parsed.login, e.login);
+        assert_eq!(parsed.password, e.password);
+        assert_eq!(parsed.tags, e.first_tag().map_or(0, |tag| 1 << tag));
+    }
+
+    let edited = pass::parse_entry(
+        Path::new("banking/bank.gpg"),
+        "secret\nurl: https://bank.example.com\nlogin:  bob \n",
+    );
+    assert_eq!(edited.login, "bob");
+    assert_eq!(edited.tags, TAG_BANKING);
+}
+
+#[test]
+fn pass_round_trip() {
+    if Command::new("gpg").arg("--version").output().is_err() {
+        eprintln!("gpg not found, skipping");
+        return;
+    }
+    let home = tempfile::tempdir().unwrap();
+    let status = Command::new("gpg")
+        .env("GNUPGHOME", home.path())
+        .args(["--quiet", "--batch", "--passphrase", ""])
+        .args(["--quick-gen-key", "test@nanopass", "default", "default"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let gpg = pass::Gpg::with_home(home.path());
+
+    let store = tempfile::tempdir().unwrap();
+    let entries = sample_entries();
+    assert!(matches!(
+        pass::write_store(store.path(), &entries, &gpg),
+        Err(Error::Format(_))
+    ));
+    std::fs::write(store.path().join(".gpg-id"), "test@nanopass\n").unwrap();
+    pass::write_store(store.path(), &entries, &gpg).unwrap();
+    assert!(pass::write_store(store.path(), &entries[..1], &gpg).is_err());
+
+    let mut parsed = pass::read_store(store.path(), &gpg).unwrap();
+    assert!(parsed.skipped.is_empty());
+    for e in parsed.entries.iter_mut() {
+        assert!(e.updated >= NOW);
+        e.created = 0;
+        e.updated = 0;
+    }
+    parsed.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut expected: Vec<_> = without_attributes(&entries)
+        .into_iter()
+        .map(|e| Entry {
+            created: 0,
+            updated: 0,
+            ..e
+        })
+        .collect();
+    expected.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(parsed.entries, expected);
+}
@@ -0,0 +1,304 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nanopass_client::transport::Transport;
+use nanopass_client::Client;
+use nanopass_migrate::device;
+use nanopass_migrate::entry::{self, Entry, Field, Problem, ENTRY_LEN};
+use nanopass_migrate::formats::{Format, Parsed};
+use nanopass_migrate::Error;
+use std::path::Path;
+
+const NOW: u32 = 1700000000;
+
+const TAG_WORK: u8 = 0x01;
+const TAG_PERSONAL: u8 = 0x02;
+const TAG_BANKING: u8 = 0x08;
+const TAG_SOCIAL: u8 = 0x10;
+const TAG_SHOPPING: u8 = 0x20;
+const TAG_DEV: u8 = 0x40;
+
+fn parse_sample(file: &str) -> Parsed {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/samples")
+        .join(file);
+    let format = Format::from_path(&path).unwrap();
+    format
+        .parse(&std::fs::read_to_string(path).unwrap(), NOW)
+        .unwrap()
+}
+
+fn entry(name: &str, login: &str, password: &str, tags: u8, created: u32, updated: u32) -> Entry {
+    Entry {
+        name: name.into(),
+        login: login.into(),
+        password: password.into(),
+        tags,
+        created,
+        updated,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn bitwarden_json() {
+    let parsed = parse_sample("bitwarden.json");
+    assert_eq!(parsed.skipped, ["wifi note"]);
+    assert_eq!(
+        parsed.entries[..2],
+        [
+            entry(
+                "gitlab",
+                "alice",
+                "epuu7Aeja9",
+                TAG_WORK,
+                1615680000,
+                1654041600
+            ),
+            entry("airline", "", "zexae2Moo2", 0, 1672617600, 1672617600),
+        ]
+    );
+    let (accepted, rejected) = entry::check_all(parsed.entries);
+    assert_eq!(accepted.len(), 2);
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].position, 3);
+    assert_eq!(rejected[0].name, "long password");
+    assert_eq!(rejected[0].problem, Problem::TooLong(Field::Password, 40));
+}
+
+#[test]
+fn bitwarden_encrypted() {
+    let data = r#"{"encrypted": true, "encKeyValidation_DO_NOT_EDIT": "2.x", "data": "2.y"}"#;
+    assert!(matches!(
+        Format::Bitwarden.parse(data, NOW),
+        Err(Error::Format(_))
+    ));
+}
+
+#[test]
+fn keepass_xml() {
+    let parsed = parse_sample("keepass.xml");
+    assert_eq!(parsed.skipped, ["old forum"]);
+    assert_eq!(
+        parsed.entries,
+        [
+            entry(
+                "build server",
+                "ci",
+                "quick fiber",
+                TAG_DEV,
+                1605830400,
+                1612310400
+            ),
+            entry(
+                "bank.example.com",
+                "12345678",
+                "dahTho9Thai5",
+                TAG_BANKING,
+                NOW,
+                NOW
+            ),
+        ]
+    );
+}
+
+#[test]
+fn keepassxc_csv() {
+    let parsed = parse_sample("keepassxc.csv");
+    assert!(parsed.skipped.is_empty());
+    assert_eq!(
+        parsed.entries,
+        [
+            entry(
+                "mastodon",
+                "alice",
+                "Xoh8ieN,g4",
+                TAG_SOCIAL,
+                1640995200,
+                1662768000
+            ),
+            entry("router.lan", "root", "hunter2", 0, NOW, NOW),
+        ]
+    );
+}
+
+#[test]
+fn onepassword_csv() {
+    let parsed = parse_sample("1password.csv");
+    let (accepted, rejected) = entry::check_all(parsed.entries);
+    assert_eq!(
+        accepted,
+        [
+            entry(
+                "email",
+                "alice@example.com",
+                "eeHoh3ah",
+                TAG_PERSONAL,
+                NOW,
+                NOW
+            ),
+            entry(
+                "shop, online",
+                "alice",
+                "pass\"word",
+                TAG_SHOPPING | TAG_PERSONAL,
+                NOW,
+                NOW
+            ),
+        ]
+    );
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].position, 3);
+    assert_eq!(rejected[0].problem, Problem::DuplicateName);
+}
+
+#[test]
+fn bitwarden_csv() {
+    let parsed = parse_sample("bitwarden.csv");
+    assert_eq!(parsed.skipped, ["recovery codes"]);
+    assert_eq!(
+        parsed.entries,
+        [entry("vpn", "alice", "oaw5Ohhe", TAG_WORK, NOW, NOW)]
+    );
+}
+
+#[test]
+fn csv_without_password() {
+    assert!(matches!(
+        Format::Csv.parse("name,username\na,b\n", NOW),
+        Err(Error::Format(_))
+    ));
+}
+
+#[test]
+fn check() {
+    let valid = entry("name", "login", "password", 0, NOW, NOW);
+    assert_eq!(valid.check(), Ok(()));
+    let empty = Entry {
+        name: String::new(),
+        ..valid.clone()
+    };
+    assert_eq!(empty.check(), Err(Problem::EmptyName));
+    let long_login = Entry {
+        login: "x".repeat(33),
+        ..valid.clone()
+    };
+    assert_eq!(long_login.check(), Err(Problem::TooLong(Field::Login, 33)));
+    let full = Entry {
+        name: "n".repeat(32),
+        login: "l".repeat(32),
+        password: "p".repeat(32),
+        ..valid.clone()
+    };
+    assert_eq!(full.check(), Ok(()));
+    let null = Entry {
+        password: "a\0b".into(),
+        ..valid
+    };
+    assert_eq!(null.check(), Err(Problem::NullByte(Field::Password)));
+}
+
+#[test]
+fn dates() {
+    assert_eq!(entry::parse_date("1970-01-01T00:00:00Z"), Some(0));
+    assert_eq!(entry::parse_date("2000-02-29"), Some(951782400));
+    assert_eq!(
+        entry::parse_date("2021-03-14T15:09:26.535Z"),
+        Some(1615680000)
+    );
+    assert_eq!(entry::parse_date("yesterday"), None);
+    assert_eq!(entry::parse_date("2021-13-01"), None);
+    assert_eq!(entry::parse_date(""), None);
+}
+
+#[test]
+fn host_names() {
+    assert_eq!(entry::host_name("https://example.com/login"), "example.com");
+    assert_eq!(entry::host_name("ssh://user@host.lan:22"), "host.lan");
+    assert_eq!(entry::host_name("example.com?x=1"), "example.com");
+    assert_eq!(entry::host_name(""), "");
+}
+
+/// Application stand-in, recording the APDUs it receives.
+struct Recorder {
+    version: &'static str,
+    apdus: Vec<Vec<u8>>,
+}
+
+impl Transport for Recorder {
+    fn exchange(&mut self, apdu: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.apdus.push(apdu.to_vec());
+        let mut response = Vec::new();
+        if apdu[1] == 0x01 {
+            response.extend_from_slice(&[1, 8]);
+            response.extend_from_slice(b"nanopass");
+            response.push(self.version.len() as u8);
+            response.extend_from_slice(self.version.as_bytes());
+            response.push(0);
+        }
+        response.extend_from_slice(&[0x90, 0x00]);
+        Ok(response)
+    }
+}
+
+#[test]
+fn import_apdus() {
+    let entries = [
+        entry(
+            "gitlab",
+            "alice",
+            "epuu7Aeja9",
+            TAG_WORK,
+            1615680000,
+            1654041600,
+        ),
+        entry("airline", "", "zexae2Moo2", 0, NOW, NOW),
+    ];
+    let mut recorder = Recorder {
+        version: "1.4.0",
+        apdus: Vec::new(),
+    };
+    let mut progress = Vec::new();
+    device::import(&mut Client::new(&mut recorder), &entries, |n| {
+        progress.push(n)
+    })
+    .unwrap();
+    assert_eq!(progress, [1, 2]);
+
+    let apdus = &recorder.apdus;
+    assert_eq!(apdus.len(), 4);
+    assert_eq!(apdus[1], [0x80, 0x09, 0, 0, 4, 0, 0, 0, 2]);
+    let next = &apdus[2];
+    assert_eq!(next[..5], [0x80, 0x0a, 0, 0, ENTRY_LEN as u8]);
+    let data = &next[5..];
+    assert_eq!(&data[..6], b"gitlab");
+    assert_eq!(data[6..32], [0; 26]);
+    assert_eq!(&data[32..37], b"alice");
+    assert_eq!(&data[64..74], b"epuu7Aeja9");
+    assert_eq!(data[96..98], [0, TAG_WORK]);
+    assert_eq!(data[100..104], 1615680000u32.to_be_bytes());
+    assert_eq!(data[104..108], 1654041600u32.to_be_bytes());
+    assert_eq!(data[108..], [0; 20]);
+}
+
+#[test]
+fn import_old_version() {
+    let mut recorder = Recorder {
+        version: "1.2.0",
+        apdus: Vec::new(),
+    };
+    let entries = [entry("a", "b", "c", 0, NOW, NOW)];
+    assert!(device::import(&mut Client::new(&mut recorder), &entries, |_| ()).is_err());
+    assert_eq!(recorder.apdus.len(), 1);
+}
@@ -0,0 +1,110 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export files written by `nanopass.py export`.
+//!
+//! Each encrypted entry is made of a random nonce, of the name, login,
+//! password and attributes of the entry encrypted in AES-256-CBC with the
+//! export key and the nonce as IV, and of a MAC: the last block of the
+//! encryption of the ciphertext with the same key and IV.
+
+use crate::Error;
+use aes::Aes256;
+use cbc::cipher::block_padding::NoPadding;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use serde::{Deserialize, Serialize};
+
+const BLOCK_LEN: usize = 16;
+
+/// Lengths of the plaintext of entries: name and password before version
+/// 1.1.0, name, login and password before 1.3.0, and attributes since then.
+const ENTRY_LENS: [usize; 3] = [64, 96, 128];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Export {
+    /// Version of the application.
+    pub version: String,
+    pub encrypted: bool,
+    /// Entries returned by the ExportNext APDU, in hexadecimal.
+    pub entries: Vec<String>,
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digits = std::str::from_utf8(pair).ok().filter(|d| d.len() == 2);
+            digits
+                .and_then(|d| u8::from_str_radix(d, 16).ok())
+                .ok_or_else(|| Error::Format("invalid hexadecimal entry".into()))
+        })
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decrypts an entry and verifies its MAC.
+///
+/// # Arguments
+///
+/// * `key` - Export key of the vault the entry has been exported from.
+/// * `position` - Position of the entry in the export, starting at 1, for
+///   error messages.
+pub fn decrypt_entry(key: &[u8; 32], data: &[u8], position: usize) -> Result<Vec<u8>, Error> {
+    let len = data.len().wrapping_sub(2 * BLOCK_LEN);
+    if !ENTRY_LENS.contains(&len) {
+        return Err(Error::Format(format!(
+            "invalid length {} of entry {}",
+            data.len(),
+            position
+        )));
+    }
+    let (nonce, rest) = data.split_at(BLOCK_LEN);
+    let (ciphertext, mac) = rest.split_at(len);
+    let encrypted = cbc::Encryptor::<Aes256>::new(key.into(), nonce.into())
+        .encrypt_padded_vec_mut::<NoPadding>(ciphertext);
+    if encrypted[len - BLOCK_LEN..] != *mac {
+        return Err(Error::Mac(position));
+    }
+    cbc::Decryptor::<Aes256>::new(key.into(), nonce.into())
+        .decrypt_padded_vec_mut::<NoPadding>(ciphertext)
+        .map_err(|_| Error::Format(format!("invalid length of entry {}", position)))
+}
+
+impl Export {
+    /// Decrypts all entries, returning the export the application would have
+    /// written without encryption.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Export key of the vault the entries have been exported from.
+    pub fn decrypt(&self, key: &[u8; 32]) -> Result<Export, Error> {
+        if !self.encrypted {
+            return Err(Error::Format("export is not encrypted".into()));
+        }
+        let entries = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, hex)| Ok(to_hex(&decrypt_entry(key, &from_hex(hex)?, i + 1)?)))
+            .collect::<Result<_, Error>>()?;
+        Ok(Export {
+            version: self.version.clone(),
+            encrypted: false,
+            entries,
+        })
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Derivation of export encryption keys from the recovery phrase.
+//!
+//! The application derives them with `ecc::bip32_derive` on the secp256k1
+//! curve, which returns the BIP32 private key of a path: the key of the first
+//! vault is the one of the SLIP16 path `m/10016'/0`, and the key of vault `i`
+//! the one of its hardened child `i`.
+
+use crate::Error;
+use hmac::{Hmac, Mac};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::elliptic_curve::PrimeField;
+use k256::{FieldBytes, ProjectivePoint, Scalar};
+use sha2::Sha512;
+use zeroize::Zeroizing;
+
+/// Flag of hardened derivation indexes.
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// SLIP16 path of the export encryption key of the first vault.
+pub const EXPORT_PATH: [u32; 2] = [HARDENED | 10016, 0];
+
+/// Number of vaults of the application.
+pub const MAX_VAULTS: u8 = 4;
+
+type HmacSha512 = Hmac<Sha512>;
+
+/// Computes the BIP39 seed of a recovery phrase.
+///
+/// # Arguments
+///
+/// * `mnemonic` - Recovery phrase, in English.
+/// * `passphrase` - Passphrase the device is unlocked with, or an empty
+///   string.
+pub fn seed(mnemonic: &str, passphrase: &str) -> Result<Zeroizing<[u8; 64]>, Error> {
+    let mnemonic = bip39::Mnemonic::parse_normalized(mnemonic)?;
+    Ok(Zeroizing::new(mnemonic.to_seed(passphrase)))
+}
+
+/// Splits the output of HMAC-SHA512 into a private key, added to `parent`,
+/// and a chain code.
+fn child(hmac: HmacSha512, parent: &Scalar) -> Result<(Scalar, [u8; 32]), Error> {
+    let output = Zeroizing::new(hmac.finalize().into_bytes());
+    let tweak: Option<Scalar> = Scalar::from_repr(*FieldBytes::from_slice(&output[..32])).into();
+    let key = tweak.ok_or(Error::InvalidKey)? + parent;
+    if bool::from(key.is_zero()) {
+        return Err(Error::InvalidKey);
+    }
+    Ok((key, output[32..].try_into().unwrap()))
+}
+
+/// Derives the BIP32 private key of a path on the secp256k1 curve.
+///
+/// # Arguments
+///
+/// * `path` - Derivation indexes, with `HARDENED` set for hardened ones.
+pub fn derive(seed: &[u8], path: &[u32]) -> Result<Zeroizing<[u8; 32]>, Error> {
+    let mut hmac = HmacSha512::new_from_slice(b"Bitcoin seed").unwrap();
+    hmac.update(seed);
+    let (mut key, mut chain_code) = child(hmac, &Scalar::ZERO)?;
+    for index in path {
+        let mut hmac = HmacSha512::new_from_slice(&chain_code).unwrap();
+        if index & HARDENED != 0 {
+            hmac.update(&[0]);
+            hmac.update(&Zeroizing::new(key.to_bytes()));
+        } else {
+            let public = (ProjectivePoint::GENERATOR * key).to_affine();
+            hmac.update(public.to_encoded_point(true).as_bytes());
+        }
+        hmac.update(&index.to_be_bytes());
+        (key, chain_code) = child(hmac, &key)?;
+    }
+    Ok(Zeroizing::new(key.to_bytes().into()))
+}
+
+/// Derives the export encryption key of a vault.
+///
+/// # Arguments
+///
+/// * `vault` - Index of the vault, from 0 to `MAX_VAULTS - 1`.
+pub fn export_key(seed: &[u8], vault: u8) -> Result<Zeroizing<[u8; 32]>, Error> {
+    match vault {
+        0 => derive(seed, &EXPORT_PATH),
+        _ => derive(
+            seed,
+            &[EXPORT_PATH[0], EXPORT_PATH[1], HARDENED | vault as u32],
+        ),
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decryption of encrypted exports with the recovery phrase of the device
+//! which made them, for instance after the device has been lost.
+
+pub mod export;
+pub mod keys;
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The recovery phrase is not a valid BIP39 mnemonic.
+    Mnemonic(bip39::Error),
+    /// A derived key is invalid, which happens for less than one path in
+    /// 2^127.
+    InvalidKey,
+    /// The MAC of the entry at this position, starting at 1, does not match.
+    Mac(usize),
+    /// The content of a file is not as expected.
+    Format(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "invalid JSON: {}", e),
+            Error::Mnemonic(e) => write!(f, "invalid recovery phrase: {}", e),
+            Error::InvalidKey => f.write_str("invalid derived key"),
+            Error::Mac(position) => write!(
+                f,
+                "entry {} cannot be authenticated: wrong recovery phrase, \
+                 passphrase or vault, or corrupted export",
+                position
+            ),
+            Error::Format(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<bip39::Error> for Error {
+    fn from(e: bip39::Error) -> Error {
+        Error::Mnemonic(e)
+    }
+}
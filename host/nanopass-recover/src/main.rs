@@ -0,0 +1,92 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Parser;
+use nanopass_recover::export::Export;
+use nanopass_recover::{keys, Error};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+/// Decrypt an encrypted export with the recovery phrase of the device which
+/// made it. The recovery phrase, and the passphrase if any, are read from the
+/// standard input.
+#[derive(Parser)]
+#[command(about)]
+struct Cli {
+    /// Encrypted export, written by `nanopass.py export`.
+    input: PathBuf,
+    /// Plaintext export to write, which can be imported by `nanopass.py
+    /// import` or converted by `nanopass-migrate export`.
+    output: PathBuf,
+    /// Vault the entries have been exported from.
+    #[arg(long, default_value_t = 0,
+          value_parser = clap::value_parser!(u8).range(..keys::MAX_VAULTS as i64))]
+    vault: u8,
+    /// Also read the passphrase the device is unlocked with.
+    #[arg(long)]
+    passphrase: bool,
+}
+
+/// Reads a line from the standard input, without its line ending.
+fn read_secret(prompt: &str) -> Result<Zeroizing<String>, Error> {
+    eprint!("{}: ", prompt);
+    std::io::stderr().flush()?;
+    let mut line = Zeroizing::new(String::new());
+    std::io::stdin().lock().read_line(&mut line)?;
+    let len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(len);
+    Ok(line)
+}
+
+/// Writes a file which only the user can read.
+fn write_private(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(data)?;
+    Ok(())
+}
+
+fn recover(cli: Cli) -> Result<(), Error> {
+    let export: Export = serde_json::from_str(&std::fs::read_to_string(&cli.input)?)?;
+    let mnemonic = read_secret("Recovery phrase")?;
+    let passphrase = match cli.passphrase {
+        true => read_secret("Passphrase")?,
+        false => Zeroizing::new(String::new()),
+    };
+    let seed = keys::seed(&mnemonic, &passphrase)?;
+    let key = keys::export_key(seed.as_ref(), cli.vault)?;
+    let plaintext = export.decrypt(&key)?;
+    let json = Zeroizing::new(serde_json::to_string_pretty(&plaintext)?);
+    write_private(&cli.output, json.as_bytes())?;
+    eprintln!(
+        "Decrypted {} entries to {}",
+        plaintext.entries.len(),
+        cli.output.display()
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match recover(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nanopass_recover::export::{self, Export};
+use nanopass_recover::keys::{self, HARDENED};
+use nanopass_recover::Error;
+use std::path::Path;
+
+/// Default recovery phrase of the Speculos emulator.
+const SPECULOS_MNEMONIC: &str = "glory promote mansion idle axis finger extra february \
+    uncover one trip resource lawn turtle enact monster seven myth punch hobby comfort wild \
+    raise skin";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sample(file: &str) -> Export {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/samples")
+        .join(file);
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+fn key(passphrase: &str, vault: u8) -> [u8; 32] {
+    let seed = keys::seed(SPECULOS_MNEMONIC, passphrase).unwrap();
+    *keys::export_key(seed.as_ref(), vault).unwrap()
+}
+
+#[test]
+fn bip32_vectors() {
+    // Test vector 1 of BIP32
+    let seed: Vec<u8> = (0..16).collect();
+    let derive = |path: &[u32]| hex(keys::derive(&seed, path).unwrap().as_ref());
+    assert_eq!(
+        derive(&[]),
+        "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35"
+    );
+    assert_eq!(
+        derive(&[HARDENED]),
+        "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea"
+    );
+    assert_eq!(
+        derive(&[HARDENED, 1]),
+        "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368"
+    );
+    assert_eq!(
+        derive(&[HARDENED, 1, HARDENED | 2, 2, 1000000000]),
+        "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8"
+    );
+}
+
+#[test]
+fn export_keys() {
+    assert_eq!(
+        hex(&key("", 0)),
+        "3cd74d36f263d14855abacddf18669935c292dc2a8f849d8721af62a9a66be2c"
+    );
+    assert_eq!(
+        hex(&key("", 1)),
+        "146502eeff49152d5e256ff121715b7e6fcae30d77e326a3a4398ff9cacc6e31"
+    );
+    assert_eq!(
+        hex(&key("passphrase", 0)),
+        "36dd1d7a3b6cd96be450135994abb5137c7b481c1898c793b9ab7d0ea8cf1aa5"
+    );
+}
+
+#[test]
+fn invalid_mnemonic() {
+    let swapped = SPECULOS_MNEMONIC.replacen("glory promote", "promote glory", 1);
+    assert!(matches!(keys::seed(&swapped, ""), Err(Error::Mnemonic(_))));
+    assert!(matches!(
+        keys::seed("glory promote mansion", ""),
+        Err(Error::Mnemonic(_))
+    ));
+}
+
+#[test]
+fn decrypt() {
+    let plaintext = sample("export.json").decrypt(&key("", 0)).unwrap();
+    assert_eq!(plaintext, sample("export.plain.json"));
+}
+
+#[test]
+fn decrypt_vaults() {
+    let vault = sample("export-vault1.json");
+    assert!(matches!(vault.decrypt(&key("", 0)), Err(Error::Mac(1))));
+    let plaintext = vault.decrypt(&key("", 1)).unwrap();
+    assert!(plaintext.entries[0].starts_with(&hex(b"emerge\0")));
+
+    let protected = sample("export-passphrase.json");
+    assert!(matches!(protected.decrypt(&key("", 0)), Err(Error::Mac(1))));
+    let plaintext = protected.decrypt(&key("passphrase", 0)).unwrap();
+    assert!(plaintext.entries[0].starts_with(&hex(b"vpn\0")));
+}
+
+#[test]
+fn decrypt_old_version() {
+    let plaintext = sample("export-1.2.json").decrypt(&key("", 0)).unwrap();
+    assert_eq!(plaintext.version, "1.2.0");
+    assert_eq!(plaintext.entries[0].len(), 2 * 96);
+    assert!(plaintext.entries[0].starts_with(&hex(b"question\0")));
+}
+
+#[test]
+fn tampered_entry() {
+    let key = key("", 0);
+    let mut export = sample("export.json");
+    let mut entry = export.entries[1].clone().into_bytes();
+    // Change the first byte of the encrypted login
+    let digit = &mut entry[2 * (16 + 32)];
+    *digit = if *digit == b'0' { b'1' } else { b'0' };
+    export.entries[1] = String::from_utf8(entry).unwrap();
+    assert!(matches!(export.decrypt(&key), Err(Error::Mac(2))));
+
+    assert!(matches!(
+        export::decrypt_entry(&key, &[0; 16 + 100 + 16], 1),
+        Err(Error::Format(_))
+    ));
+}
+
+#[test]
+fn plaintext_export() {
+    assert!(matches!(
+        sample("export.plain.json").decrypt(&key("", 0)),
+        Err(Error::Format(_))
+    ));
+}
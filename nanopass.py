@@ -19,12 +19,78 @@ from binascii import hexlify, unhexlify
 import click
 import binascii
 import json
-from typing import Optional, List, Tuple
+import time
+import urllib.request
+from datetime import date
+from typing import Callable, Optional, List, Tuple
 import ledgerwallet.client
 
 MAX_NAME_LEN = 32
 MAX_LOGIN_LEN = 32
 MAX_PASS_LEN = 32
+ATTRIBUTES_LEN = 32
+
+# Entry protection levels
+PROTECTION_CONFIRM = 0
+PROTECTION_NO_CONSENT = 1
+PROTECTION_DOUBLE_CONFIRM = 2
+PROTECTION_DISPLAY_ONLY = 3
+
+# Range API of breached passwords, queried with the first 5 hexadecimal digits
+# of the SHA-1 hash of a password.
+PWNED_RANGE_API = "https://api.pwnedpasswords.com/range/"
+
+def pwned_range(prefix: str, url: str = PWNED_RANGE_API) -> List[str]:
+    """
+    Query a range API of breached passwords.
+
+    :param prefix: First 5 hexadecimal digits of a SHA-1 hash.
+    :return: Hexadecimal suffixes of the hashes of breached passwords starting
+        with the prefix.
+    """
+    with urllib.request.urlopen(url + prefix) as r:
+        lines = r.read().decode().splitlines()
+    return [line.split(':')[0] for line in lines if line]
+
+# Fields changed by the Update command
+UPDATE_LOGIN = 0x01
+UPDATE_PASSWORD = 0x02
+UPDATE_GENERATE = 0x04
+UPDATE_PROTECTION = 0x08
+UPDATE_TAGS = 0x10
+
+# Audit findings of an entry
+AUDIT_WEAK = 0x01
+AUDIT_REUSED = 0x02
+
+# Tags an entry can be labeled with. Tags are encoded as a bit mask, bit i
+# corresponding to TAGS[i].
+TAGS = ['work', 'personal', 'infra', 'banking', 'social', 'shopping', 'dev',
+    'other']
+
+def tags_to_mask(tags) -> int:
+    mask = 0
+    for tag in tags:
+        mask |= 1 << TAGS.index(tag)
+    return mask
+
+# Keyboard layouts for typing passwords
+KEYBOARD_LAYOUTS = {
+    'us': 0,
+    'fr': 1,
+    'de': 2,
+}
+
+# Typing options
+TYPE_LOGIN_TAB = 0x01
+TYPE_ENTER = 0x02
+
+PROTECTION_LEVELS = {
+    'confirm': PROTECTION_CONFIRM,
+    'none': PROTECTION_NO_CONSENT,
+    'double': PROTECTION_DOUBLE_CONFIRM,
+    'display': PROTECTION_DISPLAY_ONLY,
+}
 
 class BadVersion(Exception):
     pass
@@ -84,20 +150,34 @@ class Client:
         value = resp[offset:offset+length]
         return value.decode()
 
-    def get_size(self) -> int:
+    def get_size(self, tags: int = 0) -> int:
         """
+        :param tags: If not zero, only entries having at least one of the tags
+            of this mask are counted.
         :return: Number of password entries.
         """
+        resp = self.dev.apdu_exchange(0x02, p1=tags)
+        # Versions prior to 1.4.0 do not return the capacity
+        assert len(resp) in (4, 8)
+        return int.from_bytes(resp[:4], 'big')
+
+    def get_capacity(self) -> int:
+        """
+        :return: Number of entries the storage can hold, at least.
+        """
         resp = self.dev.apdu_exchange(0x02)
-        assert len(resp) == 4
-        return int.from_bytes(resp, 'big')
+        assert len(resp) == 8
+        return int.from_bytes(resp[4:], 'big')
 
-    def add(self, name: str, login: str, password: Optional[str] = None):
+    def add(self, name: str, login: str, password: Optional[str] = None,
+            protection: int = PROTECTION_CONFIRM, tags: int = 0):
         """
         Add a new password.
         :param name: Password name.
         :param login: Password login.
         :param password: Password. None if it is generated by the device.
+        :param protection: Protection level of the entry.
+        :param tags: Tags mask of the entry.
         """
         name_bytes = str_to_bytes_pad(name, MAX_NAME_LEN)
         login_bytes = str_to_bytes_pad(login, MAX_LOGIN_LEN)
@@ -107,22 +187,60 @@ class Client:
         else:
             p1 = 0x01
             password_bytes = bytearray()
-        self.dev.apdu_exchange(0x03, p1=p1, data=name_bytes + login_bytes +
-            password_bytes)
+        self.dev.apdu_exchange(0x03, p1=p1, p2=protection,
+            data=name_bytes + login_bytes + password_bytes + bytes([tags]))
 
-    def get_name(self, index: int) -> str:
+    def update(self, name: str, login: Optional[str] = None,
+            password: Optional[str] = None, generate: bool = False,
+            protection: Optional[int] = None, tags: Optional[int] = None):
+        """
+        Change some fields of an existing entry, the other ones being kept.
+        Requires user consent for each changed field.
+        :param name: Password name.
+        :param login: New login, if changed.
+        :param password: New password, if changed.
+        :param generate: If True, a new password is generated by the device.
+        :param protection: New protection level, if changed.
+        :param tags: New tags mask, if changed.
+        """
+        assert password is None or not generate
+        mask = 0
+        data = str_to_bytes_pad(name, MAX_NAME_LEN)
+        if login is not None:
+            mask |= UPDATE_LOGIN
+            data += str_to_bytes_pad(login, MAX_LOGIN_LEN)
+        if password is not None:
+            mask |= UPDATE_PASSWORD
+            data += str_to_bytes_pad(password, MAX_PASS_LEN)
+        if generate:
+            mask |= UPDATE_GENERATE
+        if protection is not None:
+            mask |= UPDATE_PROTECTION
+            data += bytes([protection])
+        if tags is not None:
+            mask |= UPDATE_TAGS
+            data += bytes([tags])
+        self.dev.apdu_exchange(0x1a, data, p1=mask)
+
+    def get_name(self, index: int, tags: int = 0) -> str:
         """
         Retrieve name of a password
         :param index: Password entry index
+        :param tags: If not zero, index is among the entries having at least
+            one of the tags of this mask.
         :return: Name
         """
-        r = self.dev.apdu_exchange(0x04, index.to_bytes(4, 'big'))
+        r = self.dev.apdu_exchange(0x04, index.to_bytes(4, 'big'), p1=tags)
         assert len(r) == 32
         return bytes_to_str(r)
 
-    def get_names(self) -> List[str]:
-        """ :return: List of password names """
-        return [self.get_name(i) for i in range(self.get_size())]
+    def get_names(self, tags: int = 0) -> List[str]:
+        """
+        :param tags: If not zero, only entries having at least one of the tags
+            of this mask are listed.
+        :return: List of password names
+        """
+        return [self.get_name(i, tags) for i in range(self.get_size(tags))]
 
     def get_by_name(self, name: str) -> Tuple[str, str]:
         """
@@ -136,15 +254,35 @@ class Client:
         password = bytes_to_str(r[32:32+64])
         return (login, password)
 
-    def get_by_name_internal(self, name: str):
+    def get_by_name_internal(self, name: str, qr: bool = False):
         """
         Ask the device to display on screen the login and password with the
         given name. Using this method, no sensitive information is transfered to
         the computer.
         :param name: Password name.
+        :param qr: True to display the password as a QR code. Not supported on
+            the Nano S.
         """
         name_bytes = str_to_bytes_pad(name, MAX_NAME_LEN)
-        self.dev.apdu_exchange(0x0d, name_bytes)
+        self.dev.apdu_exchange(0x0d, name_bytes, p1=int(qr))
+
+    def type_password(self, name: str, layout: int = 0, login: bool = False,
+            enter: bool = False):
+        """
+        Ask the device to type the password with the given name, acting as a
+        USB keyboard.
+        :param name: Password name.
+        :param layout: Keyboard layout of the host.
+        :param login: True to type the login and a tab before the password.
+        :param enter: True to press Enter after the password.
+        """
+        options = 0
+        if login:
+            options |= TYPE_LOGIN_TAB
+        if enter:
+            options |= TYPE_ENTER
+        name_bytes = str_to_bytes_pad(name, MAX_NAME_LEN)
+        self.dev.apdu_exchange(0x0f, name_bytes, p1=options, p2=layout)
 
     def delete_by_name(self, name: str):
         """
@@ -154,17 +292,20 @@ class Client:
         name_bytes = str_to_bytes_pad(name, MAX_NAME_LEN)
         self.dev.apdu_exchange(0x06, name_bytes)
 
-    def export(self, encrypt: bool=True) -> List[bytes]:
+    def export(self, encrypt: bool=True, tags: int = 0) -> List[bytes]:
         """
         Export passwords.
         :param encrypt: True to encrypt passwords during export, False to export
             in plaintext.
+        :param tags: If not zero, only entries having at least one of the tags
+            of this mask are exported.
         :return: Exported entries.
         """
         p1 = 0x01
         if not encrypt:
             p1 = 0x00
-        count = int.from_bytes(self.dev.apdu_exchange(0x07, p1=p1), 'big')
+        count = int.from_bytes(self.dev.apdu_exchange(0x07, p1=p1, p2=tags),
+            'big')
         entries = []
         for i in range(count):
             entries.append(self.dev.apdu_exchange(0x08))
@@ -197,9 +338,24 @@ class Client:
                 # Patch the data blob to add login
                 assert encrypted == False
                 p = p[:32] + (b"\x00" * 32) + p[32:64]
-            assert len(p) == {True: 16+96+16, False: 96}[encrypted]
+            if version < "1.3.0":
+                # Add default attributes. Encrypted entries cannot be patched,
+                # the device accepts them without attributes.
+                if not encrypted:
+                    p = p + (b"\x00" * ATTRIBUTES_LEN)
+                assert len(p) == {True: 16+96+16, False: 96+ATTRIBUTES_LEN}[encrypted]
+            else:
+                assert len(p) == 96 + ATTRIBUTES_LEN + {True: 32, False: 0}[encrypted]
             self.dev.apdu_exchange(0x0a, p)
 
+    def rename(self, name: str, new_name: str):
+        """
+        Rename an entry, keeping its login and password. Requires user
+        consent. Fails if an entry named new_name already exists.
+        """
+        self.dev.apdu_exchange(0x19, str_to_bytes_pad(name, MAX_NAME_LEN) +
+            str_to_bytes_pad(new_name, MAX_NAME_LEN))
+
     def clear(self):
         """ Remove all passwords """
         self.dev.apdu_exchange(0x0b)
@@ -212,6 +368,130 @@ class Client:
         assert res[0] in (0, 1)
         return bool(res[0])
 
+    def get_storage_stats(self) -> dict:
+        """
+        :return: Storage usage of the active vault: number of used, free and
+            total units, highest number of writes of a unit, and estimated
+            number of writes remaining before the units wear out. Units used by
+            other vaults are counted as free.
+        """
+        resp = self.dev.apdu_exchange(0x10)
+        assert len(resp) == 20
+        keys = ('used', 'free', 'capacity', 'max_writes', 'remaining_writes')
+        # Values are counted in storage units, each entry using 1 to 3 units.
+        return {k: int.from_bytes(resp[i*4:(i+1)*4], 'big')
+                for i, k in enumerate(keys)}
+
+    def set_time(self, now: Optional[int] = None):
+        """
+        Set the current time of the device, used to date created and updated
+        entries. The device has no clock, so this must be called before
+        modifying entries.
+
+        :param now: Unix timestamp. Defaults to the time of the host.
+        """
+        if now is None:
+            now = int(time.time())
+        self.dev.apdu_exchange(0x11, now.to_bytes(4, 'big'))
+
+    def get_metadata(self, name: str) -> dict:
+        """
+        :return: Creation, modification and password change times of an
+            entry, as Unix timestamps rounded down to the day (0 if unknown),
+            and the number of times its password has been read.
+        """
+        name_bytes = str_to_bytes_pad(name, MAX_NAME_LEN)
+        resp = self.dev.apdu_exchange(0x12, name_bytes)
+        assert len(resp) == 16
+        keys = ('created', 'updated', 'accesses', 'changed')
+        return {k: int.from_bytes(resp[i*4:(i+1)*4], 'big')
+                for i, k in enumerate(keys)}
+
+    def audit(self, threshold: int = 0) -> dict:
+        """
+        Audit the stored passwords on the device. Passwords never leave the
+        device, only the findings are returned. Requires user consent.
+
+        :param threshold: Minimum entropy of a strong password, in bits. If
+            zero, the default threshold of the device is used.
+        :return: Findings (combination of AUDIT_* flags) of each entry having
+            at least one, by name.
+        """
+        names = self.get_names()
+        flags = b""
+        while len(flags) < len(names):
+            flags += self.dev.apdu_exchange(0x14,
+                len(flags).to_bytes(4, 'big'), p2=threshold)
+        assert len(flags) == len(names)
+        return {name: f for name, f in zip(names, flags) if f}
+
+    def get_duplicates(self) -> List[List[str]]:
+        """
+        Find the entries sharing the same password. Passwords are compared on
+        the device and never leave it. Requires user consent.
+
+        :return: Groups of names of entries having the same password.
+        """
+        resp = self.dev.apdu_exchange(0x15)
+        assert len(resp) == 4
+        count = int.from_bytes(resp, 'big')
+        groups = []
+        for _ in range(count):
+            resp = self.dev.apdu_exchange(0x16)
+            assert len(resp) == MAX_NAME_LEN + 2
+            group = int.from_bytes(resp[MAX_NAME_LEN:], 'big')
+            if group == len(groups):
+                groups.append([])
+            groups[group].append(bytes_to_str(resp[:MAX_NAME_LEN]))
+        return groups
+
+    def check_breach(self, name: str,
+            range_api: Callable[[str], List[str]] = pwned_range):
+        """
+        Check if a password appears in a breach database, using k-anonymity:
+        the device only returns the first 5 hexadecimal digits of the SHA-1
+        hash of the password, and compares the hashes returned by the range API
+        itself. Requires user consent, and the result is only displayed on the
+        device. Display-only entries cannot be checked.
+
+        :param name: Password name.
+        :param range_api: Returns the hexadecimal suffixes of the hashes of
+            breached passwords starting with the given prefix.
+        """
+        name_bytes = str_to_bytes_pad(name, MAX_NAME_LEN)
+        prefix = self.dev.apdu_exchange(0x17, name_bytes).decode()
+        assert len(prefix) == 5
+        hashes = [bytes.fromhex(prefix + suffix) for suffix in range_api(prefix)]
+        # Send hashes by chunks, the last one with P1 = 1
+        chunk_len = 12
+        chunks = [hashes[i:i + chunk_len]
+            for i in range(0, len(hashes), chunk_len)] or [[]]
+        for i, chunk in enumerate(chunks):
+            last = i == len(chunks) - 1
+            self.dev.apdu_exchange(0x18, b"".join(chunk), p1=int(last))
+
+    def get_stale_names(self, tags: int = 0) -> List[str]:
+        """
+        List the entries whose password has not been changed for longer than
+        the rotation age configured on the device. The time must have been set
+        with set_time.
+
+        :param tags: If not zero, only entries having at least one of the tags
+            of this mask are listed.
+        :return: List of password names
+        """
+        names = []
+        count = 1
+        while len(names) < count:
+            resp = self.dev.apdu_exchange(0x13,
+                len(names).to_bytes(4, 'big'), p1=tags)
+            count = int.from_bytes(resp[:4], 'big')
+            if len(resp) == 4:
+                break
+            assert len(resp) == 4 + MAX_NAME_LEN
+            names.append(bytes_to_str(resp[4:]))
+        return names
+
 
 @click.group()
 @click.pass_context
@@ -229,14 +509,47 @@ def version(ctx):
 @cli.command(help="Inserts a new password")
 @click.argument('name')
 @click.option('--login', default="")
+@click.option('--protection', type=click.Choice(PROTECTION_LEVELS.keys()),
+    default='confirm', help="Access protection level")
+@click.option('--tag', type=click.Choice(TAGS), multiple=True,
+    help="Tag of the entry, can be repeated")
 @click.pass_context
-def insert(ctx, name, login):
+def insert(ctx, name, login, protection, tag):
     password = input("Password (empty to generate):")
     if len(password) == 0:
         password = None
     print("Confirm password creation on your device...")
     dev = ctx.obj['DEV']
-    dev.add(name, login, password)
+    dev.set_time()
+    dev.add(name, login, password, PROTECTION_LEVELS[protection],
+        tags_to_mask(tag))
+
+@cli.command(help="Change some fields of a password")
+@click.argument('name')
+@click.option('--login', help="New login")
+@click.option('--password', is_flag=True, help="Enter a new password")
+@click.option('--generate', is_flag=True,
+    help="Generate a new password on the device")
+@click.option('--protection', type=click.Choice(PROTECTION_LEVELS.keys()),
+    help="New access protection level")
+@click.option('--tag', type=click.Choice(TAGS), multiple=True,
+    help="New tag of the entry, can be repeated")
+@click.pass_context
+def update(ctx, name, login, password, generate, protection, tag):
+    dev = ctx.obj['DEV']
+    if not dev.has_name(name):
+        print("Credentials not found")
+        return
+    if password and generate:
+        print("--password and --generate are exclusive")
+        return
+    password = input("Password:") if password else None
+    if protection is not None:
+        protection = PROTECTION_LEVELS[protection]
+    tags = tags_to_mask(tag) if tag else None
+    print("Confirm the update on your device...")
+    dev.set_time()
+    dev.update(name, login, password, generate, protection, tags)
 
 @cli.command(help="Print a stored password")
 @click.pass_context
@@ -255,20 +568,112 @@ def get(ctx, name):
 @cli.command(help="Print a stored password on the device")
 @click.pass_context
 @click.argument('name')
-def getinternal(ctx, name):
+@click.option('--qr', is_flag=True,
+    help="Display the password as a QR code (Nano S Plus and Nano X)")
+def getinternal(ctx, name, qr):
     print("Confirm password display on your device...")
     dev = ctx.obj['DEV']
-    print(dev.get_by_name_internal(name))
+    print(dev.get_by_name_internal(name, qr))
+
+@cli.command(name='type', help="Type a password with the device keyboard")
+@click.pass_context
+@click.argument('name')
+@click.option('--layout', type=click.Choice(KEYBOARD_LAYOUTS.keys()),
+    default='us', help="Keyboard layout of the host")
+@click.option('--login/--no-login', default=False,
+    help="Type the login and a tab before the password")
+@click.option('--enter/--no-enter', default=False,
+    help="Press Enter after the password")
+def type_(ctx, name, layout, login, enter):
+    print("Confirm password typing on your device...")
+    dev = ctx.obj['DEV']
+    dev.type_password(name, KEYBOARD_LAYOUTS[layout], login, enter)
 
 @cli.command(help="List the names of stored passwords")
+@click.option('--tag', type=click.Choice(TAGS), multiple=True,
+    help="Only list entries with this tag, can be repeated")
 @click.pass_context
-def list(ctx):
+def list(ctx, tag):
     dev = ctx.obj['DEV']
-    entries = dev.get_names()
+    entries = dev.get_names(tags_to_mask(tag))
     entries.sort()
     for entry in entries:
         print('-', entry)
 
+@cli.command(help="Print storage usage and wear")
+@click.pass_context
+def stats(ctx):
+    dev = ctx.obj['DEV']
+    stats = dev.get_storage_stats()
+    print(f"Used units: {stats['used']}/{stats['capacity']}")
+    print(f"Free units: {stats['free']}")
+    print(f"Entry capacity: {dev.get_capacity()}+")
+    print(f"Most written unit: {stats['max_writes']} writes")
+    print(f"Estimated remaining writes: {stats['remaining_writes']}")
+
+@cli.command(help="Print when an entry was created, updated and how often it was read")
+@click.pass_context
+@click.argument('name')
+def info(ctx, name):
+    dev = ctx.obj['DEV']
+    if not dev.has_name(name):
+        print("Credentials not found")
+        return
+    metadata = dev.get_metadata(name)
+    for key in ('created', 'updated', 'changed'):
+        day = metadata[key]
+        print(f"{key.capitalize()}:", date.fromtimestamp(day).isoformat()
+            if day else "unknown")
+    print("Accesses:", metadata['accesses'])
+
+@cli.command(help="List passwords which need rotation")
+@click.option('--tag', type=click.Choice(TAGS), multiple=True,
+    help="Only list entries with this tag, can be repeated")
+@click.pass_context
+def stale(ctx, tag):
+    dev = ctx.obj['DEV']
+    dev.set_time()
+    for name in sorted(dev.get_stale_names(tags_to_mask(tag))):
+        print('-', name, '(changed', date.fromtimestamp(
+            dev.get_metadata(name)['changed']).isoformat() + ')')
+
+@cli.command(help="Find weak and reused passwords")
+@click.option('--threshold', type=int, default=0,
+    help="Minimum entropy of a strong password, in bits")
+@click.pass_context
+def audit(ctx, threshold):
+    print("Confirm audit on your device...")
+    dev = ctx.obj['DEV']
+    findings = dev.audit(threshold)
+    for name in sorted(findings):
+        issues = [issue for flag, issue in ((AUDIT_WEAK, "weak"),
+            (AUDIT_REUSED, "reused")) if findings[name] & flag]
+        print('-', name + ':', ', '.join(issues))
+    if not findings:
+        print("No weak or reused password found")
+
+@cli.command(help="List entries sharing the same password")
+@click.pass_context
+def duplicates(ctx):
+    print("Confirm on your device...")
+    dev = ctx.obj['DEV']
+    groups = dev.get_duplicates()
+    for group in groups:
+        print('-', ', '.join(sorted(group)))
+    if not groups:
+        print("No reused password found")
+
+@cli.command(help="Check if a password has been breached")
+@click.argument('name')
+@click.option('--api', default=PWNED_RANGE_API,
+    help="URL of the range API, the hash prefix being appended")
+@click.pass_context
+def breach(ctx, name, api):
+    print("Confirm breach check on your device...")
+    dev = ctx.obj['DEV']
+    dev.check_breach(name, lambda prefix: pwned_range(prefix, api))
+    print("The result is displayed on your device")
+
 @cli.command(help="Remove a password from the store")
 @click.pass_context
 @click.argument('name')
@@ -276,13 +681,28 @@ def remove(ctx, name):
     dev = ctx.obj['DEV']
     dev.delete_by_name(name)
 
+@cli.command(help="Rename a password")
+@click.pass_context
+@click.argument('name')
+@click.argument('new_name')
+def rename(ctx, name, new_name):
+    dev = ctx.obj['DEV']
+    if dev.has_name(new_name):
+        print(f"'{new_name}' already exists")
+        return
+    print("Confirm renaming on your device...")
+    dev.set_time()
+    dev.rename(name, new_name)
+
 @cli.command(help="Export passwords to JSON file")
 @click.argument('path')
 @click.option('--encrypt/--no-encrypt', default=True)
+@click.option('--tag', type=click.Choice(TAGS), multiple=True,
+    help="Only export entries with this tag, can be repeated")
 @click.pass_context
-def export(ctx, path, encrypt):
+def export(ctx, path, encrypt, tag):
     dev = ctx.obj['DEV']
-    entries = dev.export(encrypt)
+    entries = dev.export(encrypt, tags_to_mask(tag))
     export = {
         'version': dev.get_version(),
         'encrypted': encrypt,
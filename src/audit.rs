@@ -0,0 +1,201 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Audit of the passwords of a vault, finding weak and reused passwords.
+//!
+//! Passwords are decrypted in RAM only, one or two at a time, and only the
+//! result of the audit leaves the device. Display-only entries are left out:
+//! knowing that one of them shares the password of a readable entry, or how
+//! strong its password is, would tell about a password the host must not
+//! learn.
+
+use crate::password::{ArrayString, Protection};
+use crate::storage::CAPACITY;
+use crate::strength;
+use crate::vault::Vault;
+use heapless::Vec;
+
+/// The password is weaker than the audit threshold.
+pub const FLAG_WEAK: u8 = 0x01;
+/// The password is also used by another entry of the vault.
+pub const FLAG_REUSED: u8 = 0x02;
+
+/// Maximum number of entries reported in a single response.
+pub const PAGE_LEN: usize = 128;
+
+/// Marks the first entry of a group in `Duplicates`. Indexes are lower than
+/// the capacity of the storage, so the bit is never used otherwise.
+const GROUP_START: u16 = 0x8000;
+
+/// Short digest of a password (FNV-1a, folded to 8 bits). Entries with the
+/// same password have the same fingerprint, so only entries sharing a
+/// fingerprint need to be compared.
+fn fingerprint(pass: &ArrayString<32>) -> u8 {
+    let hash = pass.bytes().iter().fold(0x811c9dc5u32, |h, &b| {
+        (h ^ b as u32).wrapping_mul(0x01000193)
+    });
+    hash.to_be_bytes().iter().fold(0, |acc, b| acc ^ b)
+}
+
+/// Audit of a vault.
+pub struct Audit<'a, 'b> {
+    vault: &'a Vault<'b>,
+    /// Fingerprint of the password of each entry of the vault.
+    fingerprints: Vec<u8, CAPACITY>,
+    /// Display-only entries, one bit per entry, bit `i % 32` of word `i / 32`.
+    excluded: [u32; (CAPACITY + 31) / 32],
+    /// Minimum entropy of a strong password, in bits.
+    threshold: u8,
+}
+
+impl<'a, 'b> Audit<'a, 'b> {
+    /// Prepares the audit of a vault. Each entry is decrypted once, except
+    /// display-only entries which are not audited.
+    ///
+    /// # Arguments
+    ///
+    /// * `vault` - Audited vault.
+    /// * `threshold` - Minimum entropy of a strong password, in bits.
+    pub fn new(vault: &'a Vault<'b>, threshold: u8) -> Self {
+        let mut audit = Audit {
+            vault,
+            fingerprints: Vec::new(),
+            excluded: [0; (CAPACITY + 31) / 32],
+            threshold,
+        };
+        for (i, p) in vault.iter().enumerate() {
+            let fp = match p.protection() {
+                Protection::DisplayOnly => {
+                    audit.excluded[i / 32] |= 1 << (i % 32);
+                    0
+                }
+                _ => fingerprint(&p.decrypt().pass),
+            };
+            audit.fingerprints.push(fp).unwrap();
+        }
+        audit
+    }
+
+    /// Tells if the entry at the given index is left out of the audit.
+    fn is_excluded(&self, index: usize) -> bool {
+        (self.excluded[index / 32] >> (index % 32)) & 1 != 0
+    }
+
+    /// Returns the findings of the entry at the given index, as a combination
+    /// of `FLAG_*` values. Display-only entries have no findings.
+    pub fn flags(&self, index: usize) -> u8 {
+        if index >= self.fingerprints.len() || self.is_excluded(index) {
+            return 0;
+        }
+        let pass = match self.vault.get(index) {
+            Some(entry) => entry.decrypt().pass,
+            None => return 0,
+        };
+        let mut flags = 0;
+        if strength::is_weak(pass.as_str().as_bytes(), self.threshold) {
+            flags |= FLAG_WEAK;
+        }
+        if self.is_reused(index, &pass) {
+            flags |= FLAG_REUSED;
+        }
+        flags
+    }
+
+    /// Finds the entries sharing the same password, grouped by password.
+    /// Empty passwords are not considered as reused, and display-only entries
+    /// are not reported.
+    pub fn duplicates(&self) -> Duplicates {
+        let mut entries: Vec<u16, CAPACITY> = Vec::new();
+        for (i, &fp) in self.fingerprints.iter().enumerate() {
+            if self.is_excluded(i) {
+                continue;
+            }
+            let mut candidates = self
+                .fingerprints
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .filter(|&(j, &f)| f == fp && !self.is_excluded(j))
+                .peekable();
+            // Entries of a group are found along with its first entry.
+            if candidates.peek().is_none() || entries.iter().any(|&e| e & !GROUP_START == i as u16)
+            {
+                continue;
+            }
+            let pass = self.vault.get(i).unwrap().decrypt().pass;
+            if pass.len() == 0 {
+                continue;
+            }
+            let mut group_start = GROUP_START;
+            for (j, _) in candidates {
+                if self.vault.get(j).unwrap().decrypt().pass == pass {
+                    if group_start != 0 {
+                        entries.push(i as u16 | group_start).unwrap();
+                        group_start = 0;
+                    }
+                    entries.push(j as u16).unwrap();
+                }
+            }
+        }
+        Duplicates { entries }
+    }
+
+    /// Tells if another entry than the one at the given index has the same
+    /// password. Empty passwords are not considered as reused, and
+    /// display-only entries are not compared.
+    fn is_reused(&self, index: usize, pass: &ArrayString<32>) -> bool {
+        let fp = self.fingerprints[index];
+        pass.len() != 0
+            && self.fingerprints.iter().enumerate().any(|(i, &f)| {
+                i != index
+                    && f == fp
+                    && !self.is_excluded(i)
+                    && self.vault.get(i).unwrap().decrypt().pass == *pass
+            })
+    }
+}
+
+/// Entries sharing their password with other entries.
+///
+/// Only indexes are kept, to save RAM.
+pub struct Duplicates {
+    /// Indexes of the entries, the entries of a group being contiguous. The
+    /// first entry of each group is marked with `GROUP_START`.
+    entries: Vec<u16, CAPACITY>,
+}
+
+impl Duplicates {
+    /// Number of entries sharing their password.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Tells if no password is reused.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the entries, returning their index in the vault and the
+    /// number of their group. Groups are numbered from zero.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.entries
+            .iter()
+            .scan(None, |group: &mut Option<usize>, &e| {
+                if e & GROUP_START != 0 {
+                    *group = Some(group.map_or(0, |g| g + 1));
+                }
+                Some(((e & !GROUP_START) as usize, group.unwrap_or(0)))
+            })
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! USB HID keyboard emulation, used to type passwords in the focused field of
+//! the host.
+//!
+//! The device translates each character into a key press according to the
+//! keyboard layout configured on the host. Only printable ASCII characters can
+//! be typed.
+//!
+//! Reports are sent on the keyboard interface of the C SDK, which is declared
+//! next to the HID interface carrying APDUs when `HAVE_USB_HIDKBD` is defined
+//! (see `.cargo/config.toml`).
+
+use core::convert::TryFrom;
+
+extern "C" {
+    fn io_usb_send_ep(ep: u32, buffer: *mut u8, length: u16, timeout: u32);
+}
+
+/// IN endpoint of the HID keyboard interface declared by the C SDK.
+const KEYBOARD_ENDPOINT: u32 = 0x82;
+/// Timeout for sending a report on the USB bus.
+const SEND_TIMEOUT: u32 = 20;
+
+/// Modifier bits of the HID keyboard report
+const SHIFT: u8 = 0x02;
+const ALT_GR: u8 = 0x40;
+
+/// HID usage codes for special keys
+const KEY_ENTER: u8 = 0x28;
+const KEY_TAB: u8 = 0x2b;
+const KEY_SPACE: u8 = 0x2c;
+
+/// Type the login followed by a tab before the password.
+pub const OPTION_LOGIN_TAB: u8 = 0x01;
+/// Press Enter after the password.
+pub const OPTION_ENTER: u8 = 0x02;
+
+/// Keyboard layout configured on the host.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    Us = 0,
+    Fr = 1,
+    De = 2,
+}
+
+/// All supported layouts, in the order they are displayed in the settings.
+pub const LAYOUTS: [Layout; 3] = [Layout::Us, Layout::Fr, Layout::De];
+
+impl TryFrom<u8> for Layout {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Self::Us),
+            1 => Ok(Self::Fr),
+            2 => Ok(Self::De),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Layout {
+    /// Name of the layout, as displayed in the settings.
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "US keyboard",
+            Layout::Fr => "FR keyboard",
+            Layout::De => "DE keyboard",
+        }
+    }
+
+    /// Returns the key to be pressed to type a character, or None if the
+    /// character cannot be typed with this layout.
+    fn key(self, c: u8) -> Option<Key> {
+        match self {
+            Layout::Us => key_us(c),
+            Layout::Fr => key_fr(c),
+            Layout::De => key_de(c),
+        }
+    }
+}
+
+/// A key press, with its modifiers.
+#[derive(Clone, Copy)]
+struct Key {
+    modifiers: u8,
+    code: u8,
+    /// Dead keys must be followed by a space to produce the character.
+    dead: bool,
+}
+
+const fn k(modifiers: u8, code: u8) -> Option<Key> {
+    Some(Key {
+        modifiers,
+        code,
+        dead: false,
+    })
+}
+
+const fn dead(modifiers: u8, code: u8) -> Option<Key> {
+    Some(Key {
+        modifiers,
+        code,
+        dead: true,
+    })
+}
+
+/// HID usage code of a letter key, as printed on a QWERTY keyboard.
+fn letter(c: u8) -> u8 {
+    0x04 + (c.to_ascii_lowercase() - b'a')
+}
+
+/// HID usage code of a digit key from the top row.
+fn digit(c: u8) -> u8 {
+    match c {
+        b'0' => 0x27,
+        _ => 0x1e + (c - b'1'),
+    }
+}
+
+fn key_us(c: u8) -> Option<Key> {
+    match c {
+        b'a'..=b'z' => k(0, letter(c)),
+        b'A'..=b'Z' => k(SHIFT, letter(c)),
+        b'0'..=b'9' => k(0, digit(c)),
+        b' ' => k(0, KEY_SPACE),
+        b'!' => k(SHIFT, 0x1e),
+        b'"' => k(SHIFT, 0x34),
+        b'#' => k(SHIFT, 0x20),
+        b'$' => k(SHIFT, 0x21),
+        b'%' => k(SHIFT, 0x22),
+        b'&' => k(SHIFT, 0x24),
+        b'\'' => k(0, 0x34),
+        b'(' => k(SHIFT, 0x26),
+        b')' => k(SHIFT, 0x27),
+        b'*' => k(SHIFT, 0x25),
+        b'+' => k(SHIFT, 0x2e),
+        b',' => k(0, 0x36),
+        b'-' => k(0, 0x2d),
+        b'.' => k(0, 0x37),
+        b'/' => k(0, 0x38),
+        b':' => k(SHIFT, 0x33),
+        b';' => k(0, 0x33),
+        b'<' => k(SHIFT, 0x36),
+        b'=' => k(0, 0x2e),
+        b'>' => k(SHIFT, 0x37),
+        b'?' => k(SHIFT, 0x38),
+        b'@' => k(SHIFT, 0x1f),
+        b'[' => k(0, 0x2f),
+        b'\\' => k(0, 0x31),
+        b']' => k(0, 0x30),
+        b'^' => k(SHIFT, 0x23),
+        b'_' => k(SHIFT, 0x2d),
+        b'`' => k(0, 0x35),
+        b'{' => k(SHIFT, 0x2f),
+        b'|' => k(SHIFT, 0x31),
+        b'}' => k(SHIFT, 0x30),
+        b'~' => k(SHIFT, 0x35),
+        _ => None,
+    }
+}
+
+fn key_fr(c: u8) -> Option<Key> {
+    match c {
+        // Letters which are not at the same place as on QWERTY keyboards
+        b'a' => k(0, letter(b'q')),
+        b'A' => k(SHIFT, letter(b'q')),
+        b'q' => k(0, letter(b'a')),
+        b'Q' => k(SHIFT, letter(b'a')),
+        b'z' => k(0, letter(b'w')),
+        b'Z' => k(SHIFT, letter(b'w')),
+        b'w' => k(0, letter(b'z')),
+        b'W' => k(SHIFT, letter(b'z')),
+        b'm' => k(0, 0x33),
+        b'M' => k(SHIFT, 0x33),
+        b'a'..=b'z' => k(0, letter(c)),
+        b'A'..=b'Z' => k(SHIFT, letter(c)),
+        b'0'..=b'9' => k(SHIFT, digit(c)),
+        b' ' => k(0, KEY_SPACE),
+        b'!' => k(0, 0x38),
+        b'"' => k(0, 0x20),
+        b'#' => k(ALT_GR, 0x20),
+        b'$' => k(0, 0x30),
+        b'%' => k(SHIFT, 0x34),
+        b'&' => k(0, 0x1e),
+        b'\'' => k(0, 0x21),
+        b'(' => k(0, 0x22),
+        b')' => k(0, 0x2d),
+        b'*' => k(0, 0x31),
+        b'+' => k(SHIFT, 0x2e),
+        b',' => k(0, 0x10),
+        b'-' => k(0, 0x23),
+        b'.' => k(SHIFT, 0x36),
+        b'/' => k(SHIFT, 0x37),
+        b':' => k(0, 0x37),
+        b';' => k(0, 0x36),
+        b'<' => k(0, 0x64),
+        b'=' => k(0, 0x2e),
+        b'>' => k(SHIFT, 0x64),
+        b'?' => k(SHIFT, 0x10),
+        b'@' => k(ALT_GR, 0x27),
+        b'[' => k(ALT_GR, 0x22),
+        b'\\' => k(ALT_GR, 0x25),
+        b']' => k(ALT_GR, 0x2d),
+        b'^' => k(ALT_GR, 0x26),
+        b'_' => k(0, 0x25),
+        b'`' => k(ALT_GR, 0x24),
+        b'{' => k(ALT_GR, 0x21),
+        b'|' => k(ALT_GR, 0x23),
+        b'}' => k(ALT_GR, 0x2e),
+        b'~' => k(ALT_GR, 0x1f),
+        _ => None,
+    }
+}
+
+fn key_de(c: u8) -> Option<Key> {
+    match c {
+        // Letters which are not at the same place as on QWERTY keyboards
+        b'y' => k(0, letter(b'z')),
+        b'Y' => k(SHIFT, letter(b'z')),
+        b'z' => k(0, letter(b'y')),
+        b'Z' => k(SHIFT, letter(b'y')),
+        b'a'..=b'z' => k(0, letter(c)),
+        b'A'..=b'Z' => k(SHIFT, letter(c)),
+        b'0'..=b'9' => k(0, digit(c)),
+        b' ' => k(0, KEY_SPACE),
+        b'!' => k(SHIFT, 0x1e),
+        b'"' => k(SHIFT, 0x1f),
+        b'#' => k(0, 0x31),
+        b'$' => k(SHIFT, 0x21),
+        b'%' => k(SHIFT, 0x22),
+        b'&' => k(SHIFT, 0x23),
+        b'\'' => k(SHIFT, 0x31),
+        b'(' => k(SHIFT, 0x25),
+        b')' => k(SHIFT, 0x26),
+        b'*' => k(SHIFT, 0x30),
+        b'+' => k(0, 0x30),
+        b',' => k(0, 0x36),
+        b'-' => k(0, 0x38),
+        b'.' => k(0, 0x37),
+        b'/' => k(SHIFT, 0x24),
+        b':' => k(SHIFT, 0x37),
+        b';' => k(SHIFT, 0x36),
+        b'<' => k(0, 0x64),
+        b'=' => k(SHIFT, 0x27),
+        b'>' => k(SHIFT, 0x64),
+        b'?' => k(SHIFT, 0x2d),
+        b'@' => k(ALT_GR, 0x14),
+        b'[' => k(ALT_GR, 0x25),
+        b'\\' => k(ALT_GR, 0x2d),
+        b']' => k(ALT_GR, 0x26),
+        b'^' => dead(0, 0x35),
+        b'_' => k(SHIFT, 0x38),
+        b'`' => dead(SHIFT, 0x2e),
+        b'{' => k(ALT_GR, 0x24),
+        b'|' => k(ALT_GR, 0x64),
+        b'}' => k(ALT_GR, 0x27),
+        b'~' => k(ALT_GR, 0x30),
+        _ => None,
+    }
+}
+
+/// Sends a key press followed by a key release.
+fn press(key: Key) {
+    let mut report = [key.modifiers, 0, key.code, 0, 0, 0, 0, 0];
+    unsafe {
+        io_usb_send_ep(
+            KEYBOARD_ENDPOINT,
+            report.as_mut_ptr(),
+            report.len() as u16,
+            SEND_TIMEOUT,
+        );
+    }
+    report = [0; 8];
+    unsafe {
+        io_usb_send_ep(
+            KEYBOARD_ENDPOINT,
+            report.as_mut_ptr(),
+            report.len() as u16,
+            SEND_TIMEOUT,
+        );
+    }
+}
+
+/// Tells if a string can be typed entirely with the given layout.
+pub fn can_type(layout: Layout, s: &str) -> bool {
+    s.bytes().all(|c| layout.key(c).is_some())
+}
+
+/// Types a string. Characters which cannot be typed with the layout are
+/// skipped, `can_type` should be called first.
+pub fn type_str(layout: Layout, s: &str) {
+    for key in s.bytes().filter_map(|c| layout.key(c)) {
+        press(key);
+        if key.dead {
+            press(Key {
+                modifiers: 0,
+                code: KEY_SPACE,
+                dead: false,
+            });
+        }
+    }
+}
+
+/// Presses the Tab key.
+pub fn tab() {
+    press(Key {
+        modifiers: 0,
+        code: KEY_TAB,
+        dead: false,
+    });
+}
+
+/// Presses the Enter key.
+pub fn enter() {
+    press(Key {
+        modifiers: 0,
+        code: KEY_ENTER,
+        dead: false,
+    });
+}
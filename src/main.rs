@@ -16,7 +16,6 @@
 #![no_main]
 
 use nanos_sdk::buttons::ButtonEvent;
-use nanos_sdk::ecc;
 use nanos_sdk::io;
 use nanos_sdk::io::ApduHeader;
 use nanos_sdk::io::{Reply, StatusWords};
@@ -26,25 +25,60 @@ use nanos_sdk::NVMData;
 use nanos_ui::bagls;
 use nanos_ui::layout::Draw;
 use nanos_ui::ui;
+mod audit;
 mod password;
+#[cfg(not(target_os = "nanos"))]
+mod qr;
 use heapless::Vec;
-use password::{ArrayString, PasswordItem};
+use password::{ArrayString, LegacyItem, PasswordItem, Protection, ATTRIBUTES_LEN};
+mod keyboard;
 mod tinyaes;
+use keyboard::Layout;
+mod menu;
+mod settings;
+mod sha1;
+mod storage;
+mod strength;
+mod textinput;
+mod vault;
+use audit::Audit;
 use core::convert::TryFrom;
 use core::mem::MaybeUninit;
+use nanos_sdk::nvm::SingleStorage;
+use settings::Settings;
+use storage::{Storage, CAPACITY, MAX_RECORDS};
+use vault::{Entry, LegacyStorage, Vault};
 
 nanos_sdk::set_panic!(nanos_sdk::exiting_panic);
 
+/// Stores the passwords of the versions prior to 1.4.0 in Non-Volatile Memory.
+/// It is declared first, at the place these versions used, so that their
+/// entries can be converted when the application is upgraded.
+#[link_section = ".nvm_data"]
+static mut PASSWORDS: NVMData<LegacyStorage> =
+    NVMData::new(nvm::Collection::new(LegacyItem::new()));
+
 /// Stores all passwords in Non-Volatile Memory
 #[link_section = ".nvm_data"]
-static mut PASSWORDS: NVMData<nvm::Collection<PasswordItem, 128>> =
-    NVMData::new(nvm::Collection::new(PasswordItem::new()));
+static mut STORAGE: NVMData<Storage<CAPACITY>> = NVMData::new(Storage::new());
+
+/// Stores the application settings in Non-Volatile Memory
+#[link_section = ".nvm_data"]
+static mut SETTINGS: NVMData<nvm::AtomicStorage<Settings>> =
+    NVMData::new(nvm::AtomicStorage::new(&Settings::new()));
 
 /// Possible characters for the randomly generated passwords
 static PASS_CHARS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
 
-/// SLIP16 path for password encryption (used during export/import)
-static BIP32_PATH: [u32; 2] = ecc::make_bip32_path(b"m/10016'/0");
+/// Fields changed by the Update APDU, as a mask in P1
+const UPDATE_LOGIN: u8 = 0x01;
+const UPDATE_PASSWORD: u8 = 0x02;
+/// The password is generated by the device instead of being supplied
+const UPDATE_GENERATE: u8 = 0x04;
+const UPDATE_PROTECTION: u8 = 0x08;
+const UPDATE_TAGS: u8 = 0x10;
+const UPDATE_ALL: u8 =
+    UPDATE_LOGIN | UPDATE_PASSWORD | UPDATE_GENERATE | UPDATE_PROTECTION | UPDATE_TAGS;
 
 /// App Version parameters
 const NAME: &str = env!("CARGO_PKG_NAME");
@@ -55,6 +89,9 @@ enum Error {
     StorageFull,
     EntryNotFound,
     DecryptFailed,
+    DisplayOnly,
+    CannotType,
+    NameExists,
 }
 
 impl Into<Reply> for Error {
@@ -64,6 +101,9 @@ impl Into<Reply> for Error {
             Error::StorageFull => Reply(0x9210_u16),
             Error::EntryNotFound => Reply(0x6a88_u16),
             Error::DecryptFailed => Reply(0x9d60_u16),
+            Error::DisplayOnly => Reply(0x69f1_u16),
+            Error::CannotType => Reply(0x6a8a_u16),
+            Error::NameExists => Reply(0x6a89_u16),
         }
     }
 }
@@ -83,6 +123,18 @@ enum Instruction {
     Quit,
     ShowOnScreen,
     HasName,
+    Type,
+    GetStorageStats,
+    SetTime,
+    GetMetadata,
+    GetStale,
+    Audit,
+    Duplicates,
+    DuplicatesNext,
+    BreachCheck,
+    BreachNext,
+    Rename,
+    Update,
 }
 
 impl TryFrom<ApduHeader> for Instruction {
@@ -104,6 +156,18 @@ impl TryFrom<ApduHeader> for Instruction {
             0x0c => Ok(Self::Quit),
             0x0d => Ok(Self::ShowOnScreen),
             0x0e => Ok(Self::HasName),
+            0x0f => Ok(Self::Type),
+            0x10 => Ok(Self::GetStorageStats),
+            0x11 => Ok(Self::SetTime),
+            0x12 => Ok(Self::GetMetadata),
+            0x13 => Ok(Self::GetStale),
+            0x14 => Ok(Self::Audit),
+            0x15 => Ok(Self::Duplicates),
+            0x16 => Ok(Self::DuplicatesNext),
+            0x17 => Ok(Self::BreachCheck),
+            0x18 => Ok(Self::BreachNext),
+            0x19 => Ok(Self::Rename),
+            0x1a => Ok(Self::Update),
             _ => Err(()),
         }
     }
@@ -140,15 +204,21 @@ impl Lfsr {
 extern "C" fn sample_main() {
     let mut comm = io::Comm::new();
 
-    // Don't use PASSWORDS directly in the program. It is static and using
+    // Don't use STORAGE directly in the program. It is static and using
     // it requires using unsafe everytime. Instead, take a reference here, so
     // in the rest of the program the borrow checker will be able to detect
     // misuses correctly.
-    let mut passwords = unsafe { PASSWORDS.get_mut() };
+    let passwords = unsafe { STORAGE.get_mut() };
+    let settings = unsafe { SETTINGS.get_mut() };
 
-    // Encryption/decryption key for import and export.
-    let mut enc_key = [0u8; 32];
-    let _ = ecc::bip32_derive(ecc::CurvesId::Secp256k1, &BIP32_PATH, &mut enc_key);
+    // Complete any update interrupted by a power loss, then convert the
+    // entries written by previous versions of the application.
+    passwords.recover();
+    vault::migrate(passwords);
+    vault::migrate_legacy(unsafe { PASSWORDS.get_mut() }, passwords);
+
+    // Only the entries of the active vault are accessible.
+    let mut vault = Vault::open(passwords, settings.get_ref().active_vault);
 
     // iteration counter
     let mut c = 0;
@@ -158,7 +228,7 @@ extern "C" fn sample_main() {
         match comm.next_event() {
             io::Event::Button(ButtonEvent::BothButtonsRelease) => nanos_sdk::exit_app(0),
             io::Event::Button(ButtonEvent::RightButtonRelease) => {
-                display_infos(passwords);
+                menu::display_infos(&mut vault, settings);
                 c = 0;
             }
             io::Event::Ticker => {
@@ -209,41 +279,76 @@ extern "C" fn sample_main() {
                 comm.append(&[0]); // No flags
                 comm.reply_ok();
             }
-            // Get number of stored passwords
+            // Get number of stored passwords, followed by the number of
+            // passwords of the largest size the storage can hold.
+            // If P1 is not zero, only passwords with at least one of the tags
+            // of the P1 mask are counted.
             io::Event::Command(Instruction::GetSize) => {
-                let len: [u8; 4] = passwords.len().to_be_bytes();
+                let tags = comm.get_apdu_metadata().p1;
+                let len: [u8; 4] = vault
+                    .iter()
+                    .filter(|p| p.matches_tags(tags))
+                    .count()
+                    .to_be_bytes();
                 comm.append(&len);
+                comm.append(&(MAX_RECORDS as u32).to_be_bytes());
                 comm.reply_ok();
             }
             // Add a password
             // If P1 == 0, password is in the data
             // If P1 == 1, password must be generated by the device
+            // P2 is the protection level of the entry
+            // The tags mask can be appended to the data, as an optional byte
             io::Event::Command(Instruction::Add) => {
+                let data_len = comm.get(4, 5)[0] as usize;
                 let mut offset = 5;
                 let name = ArrayString::<32>::from_bytes(comm.get(offset, offset + 32));
                 offset += 32;
                 let login = ArrayString::<32>::from_bytes(comm.get(offset, offset + 32));
                 offset += 32;
                 let pass = match comm.get_apdu_metadata().p1 {
-                    0 => Some(ArrayString::<32>::from_bytes(comm.get(offset, offset + 32))),
+                    0 => {
+                        offset += 32;
+                        Some(ArrayString::<32>::from_bytes(comm.get(offset - 32, offset)))
+                    }
                     _ => None,
                 };
-                comm.reply::<Reply>(match set_password(passwords, &name, &login, &pass) {
-                    Ok(()) => StatusWords::Ok.into(),
-                    Err(e) => e.into(),
-                });
+                let tags = match data_len > offset - 5 {
+                    true => comm.get(offset, offset + 1)[0],
+                    false => 0,
+                };
+                let protection = match Protection::try_from(comm.get_apdu_metadata().p2) {
+                    Ok(p) => p,
+                    Err(()) => {
+                        comm.reply(StatusWords::Unknown);
+                        continue;
+                    }
+                };
+                comm.reply::<Reply>(
+                    match set_password(&mut vault, &name, &login, &pass, protection, tags) {
+                        Ok(()) => StatusWords::Ok.into(),
+                        Err(e) => e.into(),
+                    },
+                );
                 c = 0;
             }
             // Get password name
             // This is used by the client to list the names of stored password
             // Login is not returned.
+            // If P1 is not zero, the index is among the passwords having at
+            // least one of the tags of the P1 mask.
             io::Event::Command(Instruction::GetName) => {
                 let mut index_bytes = [0; 4];
                 index_bytes.copy_from_slice(comm.get(5, 5 + 4));
                 let index = u32::from_be_bytes(index_bytes);
-                match passwords.get(index as usize) {
+                let tags = comm.get_apdu_metadata().p1;
+                match vault
+                    .iter()
+                    .filter(|p| p.matches_tags(tags))
+                    .nth(index as usize)
+                {
                     Some(password) => {
-                        comm.append(password.name.bytes());
+                        comm.append(password.name().bytes());
                         comm.reply_ok()
                     }
                     None => comm.reply(Error::EntryNotFound),
@@ -251,18 +356,18 @@ extern "C" fn sample_main() {
             }
             // Get password by name
             // Returns login and password data.
+            // Display-only entries are never returned.
             io::Event::Command(Instruction::GetByName) => {
                 let name = ArrayString::<32>::from_bytes(comm.get(5, 5 + 32));
 
-                match passwords.into_iter().find(|&&x| x.name == name) {
-                    Some(&p) => {
-                        if ui::MessageValidator::new(
-                            &[name.as_str()],
-                            &[&"Read", &"password"],
-                            &[&"Cancel"],
-                        )
-                        .ask()
-                        {
+                match vault.position(&name) {
+                    Some(index) => {
+                        let entry = vault.get(index).unwrap();
+                        if entry.protection() == Protection::DisplayOnly {
+                            comm.reply(Error::DisplayOnly);
+                        } else if ask_consent(&name, entry.protection(), &[&"Read", &"password"]) {
+                            let p = entry.decrypt();
+                            vault.record_access(index);
                             comm.append(p.login.bytes());
                             comm.append(p.pass.bytes());
                             comm.reply_ok();
@@ -282,19 +387,38 @@ extern "C" fn sample_main() {
             // to the host.
             io::Event::Command(Instruction::ShowOnScreen) => {
                 let name = ArrayString::<32>::from_bytes(comm.get(5, 5 + 32));
+                // P1 selects how the password is displayed. QR codes do not
+                // fit on the screen of the Nano S.
+                let qr_code = match comm.get_apdu_metadata().p1 {
+                    0 => false,
+                    #[cfg(not(target_os = "nanos"))]
+                    1 => true,
+                    _ => {
+                        comm.reply(StatusWords::Unknown);
+                        continue;
+                    }
+                };
 
-                match passwords.into_iter().find(|&&x| x.name == name) {
-                    Some(&p) => {
-                        if ui::MessageValidator::new(
-                            &[name.as_str()],
-                            &[&"Read", &"password"],
-                            &[&"Cancel"],
-                        )
-                        .ask()
-                        {
-                            ui::popup(p.login.as_str());
-                            ui::popup(p.pass.as_str());
-                            comm.reply_ok();
+                match vault.position(&name) {
+                    Some(index) => {
+                        let entry = vault.get(index).unwrap();
+                        if ask_consent(&name, entry.protection(), &[&"Read", &"password"]) {
+                            let p = entry.decrypt();
+                            let shown = match qr_code {
+                                #[cfg(not(target_os = "nanos"))]
+                                true => menu::show_qr(&p.pass),
+                                _ => {
+                                    ui::popup(p.login.as_str());
+                                    menu::reveal(&p.pass);
+                                    true
+                                }
+                            };
+                            if shown {
+                                vault.record_access(index);
+                                comm.reply_ok();
+                            } else {
+                                comm.reply(StatusWords::Unknown);
+                            }
                         } else {
                             ui::popup("Operation cancelled");
                             comm.reply(Error::NoConsent);
@@ -311,16 +435,15 @@ extern "C" fn sample_main() {
             // Delete password by name
             io::Event::Command(Instruction::DeleteByName) => {
                 let name = ArrayString::<32>::from_bytes(comm.get(5, 5 + 32));
-                match passwords.into_iter().position(|x| x.name == name) {
+                match vault.position(&name) {
                     Some(p) => {
-                        if ui::MessageValidator::new(
-                            &[name.as_str()],
+                        let protection = vault.get(p).unwrap().protection();
+                        if ask_consent(
+                            &name,
+                            protection.for_modification(),
                             &[&"Remove", &"password"],
-                            &[&"Cancel"],
-                        )
-                        .ask()
-                        {
-                            passwords.remove(p);
+                        ) {
+                            vault.remove(p);
                             comm.reply_ok();
                         } else {
                             comm.reply(Error::NoConsent);
@@ -335,11 +458,16 @@ extern "C" fn sample_main() {
             }
             // Export
             // P1 can be 0 for plaintext, 1 for encrypted export.
-            io::Event::Command(Instruction::Export) => match comm.get_apdu_metadata().p1 {
-                0 => export(&mut comm, &passwords, None),
-                1 => export(&mut comm, &passwords, Some(&enc_key)),
-                _ => comm.reply(StatusWords::Unknown),
-            },
+            // If P2 is not zero, only passwords with at least one of the tags
+            // of the P2 mask are exported.
+            io::Event::Command(Instruction::Export) => {
+                let tags = comm.get_apdu_metadata().p2;
+                match comm.get_apdu_metadata().p1 {
+                    0 => export(&mut comm, &vault, None, tags),
+                    1 => export(&mut comm, &vault, Some(vault.key()), tags),
+                    _ => comm.reply(StatusWords::Unknown),
+                }
+            }
             // Reserved for export
             io::Event::Command(Instruction::ExportNext) => {
                 comm.reply(StatusWords::Unknown);
@@ -347,8 +475,11 @@ extern "C" fn sample_main() {
             // Import
             // P1 can be 0 for plaintext, 1 for encrypted import.
             io::Event::Command(Instruction::Import) => match comm.get_apdu_metadata().p1 {
-                0 => import(&mut comm, &mut passwords, None),
-                1 => import(&mut comm, &mut passwords, Some(&enc_key)),
+                0 => import(&mut comm, &mut vault, None),
+                1 => {
+                    let enc_key = *vault.key();
+                    import(&mut comm, &mut vault, Some(&enc_key))
+                }
                 _ => comm.reply(StatusWords::Unknown),
             },
             // Reserved for import
@@ -364,7 +495,7 @@ extern "C" fn sample_main() {
                         if ui::MessageValidator::new(&[], &[&"Are you", &"sure?"], &[&"Cancel"])
                             .ask()
                         {
-                            passwords.clear();
+                            vault.clear();
                             StatusWords::Ok.into()
                         } else {
                             Error::NoConsent.into()
@@ -383,7 +514,7 @@ extern "C" fn sample_main() {
             // HasName
             io::Event::Command(Instruction::HasName) => {
                 let name = ArrayString::<32>::from_bytes(comm.get(5, 5 + 32));
-                match passwords.into_iter().find(|&&x| x.name == name) {
+                match vault.find(&name) {
                     Some(_) => {
                         comm.append(&[1]);
                     }
@@ -393,44 +524,216 @@ extern "C" fn sample_main() {
                 }
                 comm.reply_ok();
             }
+            // Type login and password with the USB keyboard
+            // P1 is a combination of typing options
+            // P2 is the keyboard layout of the host
+            io::Event::Command(Instruction::Type) => {
+                let name = ArrayString::<32>::from_bytes(comm.get(5, 5 + 32));
+                let options = comm.get_apdu_metadata().p1;
+                let layout = match Layout::try_from(comm.get_apdu_metadata().p2) {
+                    Ok(l) => l,
+                    Err(()) => {
+                        comm.reply(StatusWords::Unknown);
+                        continue;
+                    }
+                };
+                comm.reply::<Reply>(match vault.position(&name) {
+                    Some(index) => {
+                        let result = type_password(&vault.get(index).unwrap(), options, layout);
+                        match result {
+                            Ok(()) => {
+                                vault.record_access(index);
+                                StatusWords::Ok.into()
+                            }
+                            Err(e) => e.into(),
+                        }
+                    }
+                    None => Error::EntryNotFound.into(),
+                });
+                c = 0;
+            }
+            // Storage usage of the active vault, and wear of the storage
+            io::Event::Command(Instruction::GetStorageStats) => {
+                let stats = vault.stats();
+                comm.append(&(stats.used as u32).to_be_bytes());
+                comm.append(&(stats.free as u32).to_be_bytes());
+                comm.append(&(stats.capacity as u32).to_be_bytes());
+                comm.append(&stats.max_writes.to_be_bytes());
+                comm.append(&stats.remaining_writes.to_be_bytes());
+                comm.reply_ok();
+            }
+            // Set the current time, used to date entries. The device has no
+            // clock, so the host sends it before modifying entries.
+            io::Event::Command(Instruction::SetTime) => {
+                let mut time_bytes = [0; 4];
+                time_bytes.copy_from_slice(comm.get(5, 5 + 4));
+                vault.set_time(u32::from_be_bytes(time_bytes));
+                comm.reply_ok();
+            }
+            // Get creation and modification times, access count and password
+            // change time of an entry. Secrets are not involved, so no consent
+            // is asked.
+            io::Event::Command(Instruction::GetMetadata) => {
+                let name = ArrayString::<32>::from_bytes(comm.get(5, 5 + 32));
+                match vault.find(&name) {
+                    Some(entry) => {
+                        comm.append(&entry.created().to_be_bytes());
+                        comm.append(&entry.updated().to_be_bytes());
+                        comm.append(&entry.accesses().to_be_bytes());
+                        comm.append(&entry.changed().to_be_bytes());
+                        comm.reply_ok();
+                    }
+                    None => comm.reply(Error::EntryNotFound),
+                }
+            }
+            // Get the number of entries which need rotation, according to
+            // the rotation age set in the device settings, followed by the name
+            // of the n-th of them if it exists.
+            // If P1 is not zero, only entries with at least one of the tags of
+            // the P1 mask are taken into account.
+            io::Event::Command(Instruction::GetStale) => {
+                let mut index_bytes = [0; 4];
+                index_bytes.copy_from_slice(comm.get(5, 5 + 4));
+                let index = u32::from_be_bytes(index_bytes) as usize;
+                let tags = comm.get_apdu_metadata().p1;
+                let days = settings.get_ref().rotation_days;
+                let count = vault.stale(days).filter(|p| p.matches_tags(tags)).count();
+                comm.append(&(count as u32).to_be_bytes());
+                if let Some(password) = vault
+                    .stale(days)
+                    .filter(|p| p.matches_tags(tags))
+                    .nth(index)
+                {
+                    comm.append(password.name().bytes());
+                }
+                comm.reply_ok();
+            }
+            // Audit the passwords of the vault, after user consent.
+            // Returns one byte of findings (see `audit::FLAG_*`) for each
+            // entry, starting at the index given in the data.
+            // P2 is the minimum entropy of a strong password, in bits. If
+            // zero, the default threshold is used.
+            io::Event::Command(Instruction::Audit) => {
+                let mut index_bytes = [0; 4];
+                index_bytes.copy_from_slice(comm.get(5, 5 + 4));
+                let start = u32::from_be_bytes(index_bytes) as usize;
+                let threshold = match comm.get_apdu_metadata().p2 {
+                    0 => strength::WEAK_BITS,
+                    t => t,
+                };
+                if ui::MessageValidator::new(&[], &[&"Audit", &"passwords"], &[&"Cancel"]).ask() {
+                    ui::SingleMessage::new("Auditing...").show();
+                    let audit = Audit::new(&vault, threshold);
+                    for index in (start..vault.len()).take(audit::PAGE_LEN) {
+                        comm.append(&[audit.flags(index)]);
+                    }
+                    comm.reply_ok();
+                } else {
+                    comm.reply(Error::NoConsent);
+                }
+                c = 0;
+            }
+            // Reused passwords report
+            io::Event::Command(Instruction::Duplicates) => {
+                duplicates(&mut comm, &vault);
+                c = 0;
+            }
+            io::Event::Command(Instruction::DuplicatesNext) => {
+                comm.reply(StatusWords::Unknown);
+            }
+            // Check if a password has been breached
+            io::Event::Command(Instruction::BreachCheck) => {
+                let name = ArrayString::<32>::from_bytes(comm.get(5, 5 + 32));
+                match vault.find(&name) {
+                    Some(entry) => breach_check(&mut comm, &entry),
+                    None => comm.reply(Error::EntryNotFound),
+                }
+                c = 0;
+            }
+            io::Event::Command(Instruction::BreachNext) => {
+                comm.reply(StatusWords::Unknown);
+            }
+            // Rename an entry
+            // Data is the current name followed by the new name
+            io::Event::Command(Instruction::Rename) => {
+                let name = ArrayString::<32>::from_bytes(comm.get(5, 5 + 32));
+                let new_name = ArrayString::<32>::from_bytes(comm.get(5 + 32, 5 + 64));
+                comm.reply::<Reply>(match rename(&mut vault, &name, &new_name) {
+                    Ok(()) => StatusWords::Ok.into(),
+                    Err(e) => e.into(),
+                });
+                c = 0;
+            }
+            // Update some fields of an entry
+            // P1 is the mask of the changed fields (see `UPDATE_*`)
+            // Data is the name, followed by the new values of the changed
+            // fields: login, password (unless generated), protection level
+            // and tags mask
+            io::Event::Command(Instruction::Update) => {
+                let data_len = comm.get(4, 5)[0] as usize;
+                let mask = comm.get_apdu_metadata().p1;
+                // The password is either supplied or generated, not both.
+                let both = UPDATE_PASSWORD | UPDATE_GENERATE;
+                if mask & !UPDATE_ALL != 0 || mask & both == both {
+                    comm.reply(StatusWords::Unknown);
+                    continue;
+                }
+                let mut offset = 5;
+                let name = ArrayString::<32>::from_bytes(comm.get(offset, offset + 32));
+                offset += 32;
+                let login = match mask & UPDATE_LOGIN {
+                    0 => None,
+                    _ => {
+                        offset += 32;
+                        Some(ArrayString::<32>::from_bytes(comm.get(offset - 32, offset)))
+                    }
+                };
+                let pass = match mask & (UPDATE_PASSWORD | UPDATE_GENERATE) {
+                    0 => None,
+                    UPDATE_PASSWORD => {
+                        offset += 32;
+                        Some(Some(ArrayString::<32>::from_bytes(
+                            comm.get(offset - 32, offset),
+                        )))
+                    }
+                    _ => Some(None),
+                };
+                let protection = match mask & UPDATE_PROTECTION {
+                    0 => None,
+                    _ => {
+                        offset += 1;
+                        match Protection::try_from(comm.get(offset - 1, offset)[0]) {
+                            Ok(p) => Some(p),
+                            Err(()) => {
+                                comm.reply(StatusWords::Unknown);
+                                continue;
+                            }
+                        }
+                    }
+                };
+                let tags = match mask & UPDATE_TAGS {
+                    0 => None,
+                    _ => {
+                        offset += 1;
+                        Some(comm.get(offset - 1, offset)[0])
+                    }
+                };
+                if data_len != offset - 5 {
+                    comm.reply(StatusWords::Unknown);
+                    continue;
+                }
+                comm.reply::<Reply>(
+                    match update_password(&mut vault, &name, &login, &pass, protection, tags) {
+                        Ok(()) => StatusWords::Ok.into(),
+                        Err(e) => e.into(),
+                    },
+                );
+                c = 0;
+            }
         }
     }
 }
 
-/// Conversion to a two-digit number
-fn int2dec(x: usize) -> [u8; 2] {
-    let mut t = (x % 100) as u16;
-    if t == 0 {
-        return [b' ', b'0'];
-    }
-    let mut dec = [b' '; 2];
-    dec[1] = b'0' + (t as u8) % 10;
-    t /= 10;
-    if t != 0 {
-        dec[0] = b'0' + (t as u8) % 10;
-    }
-    dec
-}
-
-/// Display global information about the app:
-/// - Current number of passwords stored
-/// - App Version
-fn display_infos(passwords: &nvm::Collection<PasswordItem, 128>) {
-    let mut stored_n = *b"   passwords";
-    let pwlen_bytes = int2dec(passwords.len());
-
-    stored_n[0] = pwlen_bytes[0];
-    stored_n[1] = pwlen_bytes[1];
-
-    // safety: int2dec returns a [u8; 2] consisting of values between
-    // '0' and '9', thus is valid utf8
-    let stored_str = unsafe { core::str::from_utf8_unchecked(&stored_n) };
-
-    const APP_VERSION_STR: &str = concat!(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-
-    ui::Menu::new(&[APP_VERSION_STR, stored_str]).show();
-}
-
 /// Generates a random password.
 ///
 /// # Arguments
@@ -446,6 +749,26 @@ fn generate_random_password(dest: &mut [u8], size: usize) {
     }
 }
 
+/// Generates a random password of 16 characters.
+fn new_random_password() -> ArrayString<32> {
+    let mut pass = [0u8; 16];
+    let len = pass.len();
+    generate_random_password(&mut pass, len);
+    let mut result = ArrayString::new();
+    result.set_from_bytes(&pass);
+    result
+}
+
+/// Returns the word designating a password on confirmation screens.
+/// Passwords supplied by the host may be weak: the user is then warned before
+/// they are stored.
+fn password_label(pass: &ArrayString<32>) -> &'static str {
+    match strength::is_weak(pass.as_str().as_bytes(), strength::WEAK_BITS) {
+        true => "weak password",
+        false => "password",
+    }
+}
+
 /// Adds or update a password in the store.
 /// Queries confirmation from the user in the UX.
 ///
@@ -454,49 +777,68 @@ fn generate_random_password(dest: &mut [u8], size: usize) {
 /// * `name` - Slice to the new name of the password. Must be 32 bytes long.
 /// * `login` - Slice to the new login of the password. Must be 32 bytes long.
 /// * `pass` - New password. If None, a password is generated automatically.
+/// * `protection` - Protection level of the password.
+/// * `tags` - Tags mask of the password.
 fn set_password(
-    passwords: &mut nvm::Collection<PasswordItem, 128>,
+    vault: &mut Vault,
     name: &ArrayString<32>,
     login: &ArrayString<32>,
     pass: &Option<ArrayString<32>>,
+    protection: Protection,
+    tags: u8,
 ) -> Result<(), Error> {
     // Create the item to be added.
     let mut new_item = PasswordItem::new();
     new_item.name = *name;
     new_item.login = *login;
-    match pass {
-        Some(a) => new_item.pass = *a,
-        None => {
-            let mut pass = [0u8; 16];
-            let len = pass.len();
-            generate_random_password(&mut pass, len);
-            new_item.pass.set_from_bytes(&pass);
-        }
-    }
+    new_item.protection = protection;
+    new_item.tags = tags;
+    new_item.created = vault.now();
+    new_item.updated = vault.now();
+    new_item.changed = vault.now();
+    new_item.pass = pass.unwrap_or_else(new_random_password);
+    let password_str = password_label(&new_item.pass);
 
-    return match passwords.into_iter().position(|x| x.name == *name) {
+    return match vault.position(name) {
         Some(index) => {
             // A password with this name already exists.
-            if !ui::MessageValidator::new(&[name.as_str()], &[&"Update", &"password"], &[&"Cancel"])
-                .ask()
-            {
+            // The protection level of the current entry applies.
+            let current = vault.get(index).unwrap();
+            if !ask_consent(
+                name,
+                current.protection().for_modification(),
+                &[&"Update", password_str],
+            ) {
                 return Err(Error::NoConsent);
             }
-            passwords.remove(index);
-            match passwords.add(&new_item) {
+            // Creation time and usage are kept from the current entry, and
+            // so is the modification time if the current time is unknown.
+            new_item.created = current.created();
+            new_item.accesses = current.accesses();
+            if vault.now() == 0 {
+                new_item.updated = current.updated();
+            }
+            // The password age is only reset if the password changes.
+            if vault.now() == 0 || current.decrypt().pass == new_item.pass {
+                new_item.changed = current.changed();
+            }
+            match vault.replace(index, &new_item) {
                 Ok(()) => Ok(()),
-                // We just removed a password, this should not happen
-                Err(nvm::StorageFullError) => panic!(),
+                Err(nvm::StorageFullError) => Err(Error::StorageFull),
             }
         }
         None => {
             // Ask user confirmation
-            if !ui::MessageValidator::new(&[name.as_str()], &[&"Create", &"password"], &[&"Cancel"])
-                .ask()
+            if !ui::MessageValidator::new(
+                &[name.as_str()],
+                &[&"Create", password_str],
+                &[&"Cancel"],
+            )
+            .ask()
             {
                 return Err(Error::NoConsent);
             }
-            match passwords.add(&new_item) {
+            match vault.add(&new_item) {
                 Ok(()) => Ok(()),
                 Err(nvm::StorageFullError) => Err(Error::StorageFull),
             }
@@ -504,16 +846,205 @@ fn set_password(
     };
 }
 
+/// Changes some fields of an existing entry, the other ones being kept.
+/// Each changed field is confirmed on a dedicated screen, the first one
+/// according to the protection level of the entry, or to the new one if it
+/// requires more confirmations. The protection of display-only entries cannot
+/// be lowered, as their password would become readable by the host.
+///
+/// # Arguments
+///
+/// * `name` - Name of the entry.
+/// * `login` - New login, if changed.
+/// * `pass` - New password, if changed. If `Some(None)`, a password is
+///   generated automatically.
+/// * `protection` - New protection level, if changed.
+/// * `tags` - New tags mask, if changed.
+fn update_password(
+    vault: &mut Vault,
+    name: &ArrayString<32>,
+    login: &Option<ArrayString<32>>,
+    pass: &Option<Option<ArrayString<32>>>,
+    protection: Option<Protection>,
+    tags: Option<u8>,
+) -> Result<(), Error> {
+    let index = vault.position(name).ok_or(Error::EntryNotFound)?;
+    let current = vault.get(index).unwrap().protection();
+    if current == Protection::DisplayOnly
+        && matches!(protection, Some(p) if p != Protection::DisplayOnly)
+    {
+        return Err(Error::DisplayOnly);
+    }
+    let new_pass = pass.map(|p| p.unwrap_or_else(new_random_password));
+
+    let mut screens: Vec<[&str; 2], 4> = Vec::new();
+    if login.is_some() {
+        screens.push(["Update", "login"]).unwrap();
+    }
+    match (pass, &new_pass) {
+        (Some(None), _) => screens.push(["Regenerate", "password"]).unwrap(),
+        (_, Some(p)) => screens.push(["Update", password_label(p)]).unwrap(),
+        _ => (),
+    }
+    if let Some(p) = protection {
+        screens.push(["Protection", p.label()]).unwrap();
+    }
+    if tags.is_some() {
+        screens.push(["Update", "tags"]).unwrap();
+    }
+    if screens.is_empty() {
+        return Ok(());
+    }
+
+    // The stricter of the current and new protection levels applies to the
+    // first screen.
+    let level = protection.map_or(current, |p| current.stricter(p));
+    for (i, action) in screens.iter().enumerate() {
+        let accepted = match i {
+            0 => ask_consent(name, level.for_modification(), action),
+            _ => ui::MessageValidator::new(&[name.as_str()], action, &[&"Cancel"]).ask(),
+        };
+        if !accepted {
+            return Err(Error::NoConsent);
+        }
+    }
+
+    // The entry is decrypted only once the user has consented.
+    let mut item = vault.get(index).unwrap().decrypt();
+    if let Some(l) = login {
+        item.login = *l;
+    }
+    if let Some(p) = new_pass {
+        item.pass = p;
+    }
+    if let Some(p) = protection {
+        item.protection = p;
+    }
+    if let Some(t) = tags {
+        item.tags = t;
+    }
+    if vault.now() != 0 {
+        item.updated = vault.now();
+        // The password age is only reset if the password changes.
+        if new_pass.is_some() {
+            item.changed = vault.now();
+        }
+    }
+    match vault.replace(index, &item) {
+        Ok(()) => Ok(()),
+        Err(nvm::StorageFullError) => Err(Error::StorageFull),
+    }
+}
+
+/// Renames an entry, after user consent. Both names are displayed for
+/// confirmation.
+///
+/// # Arguments
+///
+/// * `name` - Current name of the entry.
+/// * `new_name` - New name of the entry. No other entry may have this name.
+fn rename(
+    vault: &mut Vault,
+    name: &ArrayString<32>,
+    new_name: &ArrayString<32>,
+) -> Result<(), Error> {
+    let index = vault.position(name).ok_or(Error::EntryNotFound)?;
+    if vault.position(new_name).is_some() {
+        return Err(Error::NameExists);
+    }
+    let protection = vault.get(index).unwrap().protection();
+    if !ask_consent(
+        name,
+        protection.for_modification(),
+        &[&"Rename", &"password"],
+    ) || !ui::MessageValidator::new(
+        &[&"New name", new_name.as_str()],
+        &[&"Confirm"],
+        &[&"Cancel"],
+    )
+    .ask()
+    {
+        return Err(Error::NoConsent);
+    }
+    match vault.rename(index, new_name) {
+        Ok(()) => Ok(()),
+        Err(nvm::StorageFullError) => Err(Error::StorageFull),
+    }
+}
+
+/// Types the password of an entry with the USB keyboard, after user consent.
+///
+/// # Arguments
+///
+/// * `entry` - Entry to be typed.
+/// * `options` - Typing options (see `keyboard::OPTION_*`).
+/// * `layout` - Keyboard layout of the host.
+fn type_password(entry: &Entry, options: u8, layout: Layout) -> Result<(), Error> {
+    // Keystrokes are received by the host, so display-only entries are never
+    // typed.
+    if entry.protection() == Protection::DisplayOnly {
+        return Err(Error::DisplayOnly);
+    }
+    if !ask_consent(&entry.name(), entry.protection(), &[&"Type", &"password"]) {
+        return Err(Error::NoConsent);
+    }
+    // The entry is decrypted only once the user has consented.
+    let item = entry.decrypt();
+    let with_login = options & keyboard::OPTION_LOGIN_TAB != 0;
+    if !keyboard::can_type(layout, item.pass.as_str())
+        || (with_login && !keyboard::can_type(layout, item.login.as_str()))
+    {
+        return Err(Error::CannotType);
+    }
+    ui::SingleMessage::new("Typing...").show();
+    if with_login {
+        keyboard::type_str(layout, item.login.as_str());
+        keyboard::tab();
+    }
+    keyboard::type_str(layout, item.pass.as_str());
+    if options & keyboard::OPTION_ENTER != 0 {
+        keyboard::enter();
+    }
+    Ok(())
+}
+
+/// Asks user consent to access an entry, according to a protection level.
+///
+/// # Arguments
+///
+/// * `name` - Name of the entry, displayed to the user.
+/// * `protection` - Protection level to be enforced.
+/// * `action` - Action displayed on the confirmation screen.
+fn ask_consent(name: &ArrayString<32>, protection: Protection, action: &[&str]) -> bool {
+    match protection {
+        Protection::NoConsent => true,
+        Protection::Confirm | Protection::DisplayOnly => {
+            ui::MessageValidator::new(&[name.as_str()], action, &[&"Cancel"]).ask()
+        }
+        Protection::DoubleConfirm => {
+            ui::MessageValidator::new(&[name.as_str()], action, &[&"Cancel"]).ask()
+                && ui::MessageValidator::new(
+                    &[&"Protected entry", name.as_str()],
+                    &[&"Confirm"],
+                    &[&"Cancel"],
+                )
+                .ask()
+        }
+    }
+}
+
 /// Export procedure.
 ///
+/// Display-only entries are exported only when encryption is enabled.
+/// Entries requiring double confirmation need an additional consent to be
+/// exported in plaintext.
+///
 /// # Arguments
 ///
 /// * `enc_key` - Encryption key. If None, passwords are exported in plaintext.
-fn export(
-    comm: &mut io::Comm,
-    passwords: &nvm::Collection<PasswordItem, 128>,
-    enc_key: Option<&[u8; 32]>,
-) {
+/// * `tags` - Only passwords with at least one of the tags of this mask are
+///   exported. If zero, all passwords are exported.
+fn export(comm: &mut io::Comm, vault: &Vault, enc_key: Option<&[u8; 32]>, tags: u8) {
     // Ask user confirmation
     if !ui::MessageValidator::new(&[], &[&"Export", &"passwords"], &[&"Cancel"]).ask() {
         comm.reply(Error::NoConsent);
@@ -529,8 +1060,27 @@ fn export(
         return;
     }
 
+    let include_protected = encrypted
+        || !vault
+            .iter()
+            .any(|p| p.matches_tags(tags) && p.protection() == Protection::DoubleConfirm)
+        || ui::MessageValidator::new(
+            &[&"Export protected", &"entries?"],
+            &[&"Include"],
+            &[&"Exclude"],
+        )
+        .ask();
+    let exportable = |p: &Entry| {
+        p.matches_tags(tags)
+            && match p.protection() {
+                Protection::DisplayOnly => encrypted,
+                Protection::DoubleConfirm => include_protected,
+                _ => true,
+            }
+    };
+
     // User accepted. Reply with the number of passwords
-    let count = passwords.len();
+    let count = vault.iter().filter(exportable).count();
     comm.append(&count.to_be_bytes());
     comm.reply_ok();
 
@@ -538,22 +1088,23 @@ fn export(
     // If encryption is enabled, the IV is returned during the first iteration.
     ui::SingleMessage::new("Exporting...").show();
 
-    let mut iter = passwords.into_iter();
+    let mut iter = vault.iter().filter(exportable);
     let mut next_item = iter.next();
     while next_item.is_some() {
         match comm.next_command() {
             // Fetch next password
             Instruction::ExportNext => {
-                let password = next_item.unwrap();
+                let password = next_item.as_ref().unwrap().decrypt();
                 // If encryption is enabled, encrypt the buffer inplace.
                 if encrypted {
                     let mut nonce = [0u8; 16];
                     random::rand_bytes(&mut nonce);
                     comm.append(&nonce);
-                    let mut buffer: Vec<u8, 96> = Vec::new();
+                    let mut buffer: Vec<u8, { 96 + ATTRIBUTES_LEN }> = Vec::new();
                     buffer.extend_from_slice(password.name.bytes()).unwrap();
                     buffer.extend_from_slice(password.login.bytes()).unwrap();
                     buffer.extend_from_slice(password.pass.bytes()).unwrap();
+                    buffer.extend_from_slice(&password.attributes()).unwrap();
                     // Encrypt buffer in AES-256-CBC with random IV
                     let mut aes_ctx = MaybeUninit::<tinyaes::AES_ctx>::uninit();
                     unsafe {
@@ -588,6 +1139,7 @@ fn export(
                     comm.append(password.name.bytes());
                     comm.append(password.login.bytes());
                     comm.append(password.pass.bytes());
+                    comm.append(&password.attributes());
                 }
                 comm.reply_ok();
                 // Advance iterator.
@@ -601,16 +1153,121 @@ fn export(
     }
 }
 
+/// Reused passwords report.
+///
+/// Names of the entries sharing their password with other entries are returned
+/// one at a time, with the number of their group: entries of the same group
+/// have the same password. Passwords never leave the device.
+fn duplicates(comm: &mut io::Comm, vault: &Vault) {
+    // Ask user confirmation
+    if !ui::MessageValidator::new(&[], &[&"Find reused", &"passwords"], &[&"Cancel"]).ask() {
+        comm.reply(Error::NoConsent);
+        return;
+    }
+
+    ui::SingleMessage::new("Auditing...").show();
+    let duplicates = Audit::new(vault, strength::WEAK_BITS).duplicates();
+
+    // Reply with the number of entries, which are then retrieved with one
+    // APDU each.
+    comm.append(&(duplicates.len() as u32).to_be_bytes());
+    comm.reply_ok();
+
+    for (index, group) in duplicates.iter() {
+        match comm.next_command() {
+            Instruction::DuplicatesNext => {
+                comm.append(vault.get(index).unwrap().name().bytes());
+                comm.append(&(group as u16).to_be_bytes());
+                comm.reply_ok();
+            }
+            _ => {
+                comm.reply(StatusWords::Unknown);
+                return;
+            }
+        }
+    }
+}
+
+/// Breach check procedure, using k-anonymity.
+///
+/// Only the first 5 hexadecimal digits of the SHA-1 hash of the password are
+/// returned. The host then sends the hashes of the breached passwords starting
+/// with this prefix, as returned by a range API, and the user is told on
+/// screen whether the password is among them. Neither the password, its full
+/// hash nor the result leave the device. Display-only entries are refused, as
+/// the prefix is derived from their password.
+///
+/// # Arguments
+///
+/// * `entry` - Entry to be checked.
+fn breach_check(comm: &mut io::Comm, entry: &Entry) {
+    if entry.protection() == Protection::DisplayOnly {
+        comm.reply(Error::DisplayOnly);
+        return;
+    }
+    if !ask_consent(&entry.name(), entry.protection(), &[&"Check", &"breach"]) {
+        comm.reply(Error::NoConsent);
+        return;
+    }
+    let hash = sha1::sha1(entry.decrypt().pass.as_str().as_bytes());
+
+    // Reply with the prefix, in upper case hexadecimal as used by range APIs.
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut prefix = [0u8; 5];
+    for (i, c) in prefix.iter_mut().enumerate() {
+        *c = HEX[((hash[i / 2] >> (4 - 4 * (i % 2))) & 0xf) as usize];
+    }
+    comm.append(&prefix);
+    comm.reply_ok();
+
+    // Hashes are received in chunks, the last one having P1 set to 1.
+    ui::SingleMessage::new("Checking...").show();
+    let mut breached = false;
+    loop {
+        match comm.next_command() {
+            Instruction::BreachNext => {
+                let data_len = comm.get(4, 5)[0] as usize;
+                if data_len % sha1::HASH_LEN != 0 {
+                    comm.reply(StatusWords::Unknown);
+                    return;
+                }
+                breached |= comm
+                    .get(5, 5 + data_len)
+                    .chunks_exact(sha1::HASH_LEN)
+                    .any(|h| h == hash);
+                if comm.get_apdu_metadata().p1 == 0 {
+                    comm.reply_ok();
+                    continue;
+                }
+                ui::popup(match breached {
+                    true => "Password breached!",
+                    false => "Not breached",
+                });
+                comm.reply_ok();
+                return;
+            }
+            _ => {
+                comm.reply(StatusWords::Unknown);
+                return;
+            }
+        }
+    }
+}
+
 /// Import procedure.
 ///
+/// Entries exported by versions prior to 1.3.0 have no attributes block. They
+/// are detected from the length of the ImportNext data, and imported with
+/// default attributes.
+///
+/// An imported entry replacing an existing one keeps its protection level, so
+/// that the host cannot lower it. Replacing an entry protected by double
+/// confirmation or display-only requires user consent.
+///
 /// # Arguments
 ///
 /// * `enc_key` - Encryption key. If None, passwords are imported as plaintext.
-fn import(
-    comm: &mut io::Comm,
-    passwords: &mut nvm::Collection<PasswordItem, 128>,
-    enc_key: Option<&[u8; 32]>,
-) {
+fn import(comm: &mut io::Comm, vault: &mut Vault, enc_key: Option<&[u8; 32]>) {
     let encrypted = enc_key.is_some();
 
     // Retrieve the number of passwords to be imported
@@ -633,11 +1290,22 @@ fn import(
                 count -= 1;
                 let mut new_item = PasswordItem::new();
                 let mut decrypt_failed = false;
+                // Length of name, login, password and attributes if present.
+                let data_len = comm.get(4, 5)[0] as usize;
+                let len = match encrypted {
+                    true => data_len.wrapping_sub(32),
+                    false => data_len,
+                };
+                if len != 96 && len != 96 + ATTRIBUTES_LEN {
+                    comm.reply(StatusWords::BadLen);
+                    break;
+                }
+                let has_attributes = len == 96 + ATTRIBUTES_LEN;
                 if encrypted {
                     let nonce = comm.get(5, 5 + 16);
-                    let mut buffer: Vec<u8, 96> = Vec::new();
+                    let mut buffer: Vec<u8, { 96 + ATTRIBUTES_LEN }> = Vec::new();
                     buffer
-                        .extend_from_slice(comm.get(5 + 16, 5 + 16 + 96))
+                        .extend_from_slice(comm.get(5 + 16, 5 + 16 + len))
                         .unwrap();
                     // Decrypt with AES-256-CBC
                     let mut aes_ctx = MaybeUninit::<tinyaes::AES_ctx>::uninit();
@@ -656,10 +1324,13 @@ fn import(
                     new_item.name = ArrayString::<32>::from_bytes(&buffer[..32]);
                     new_item.login = ArrayString::<32>::from_bytes(&buffer[32..64]);
                     new_item.pass = ArrayString::<32>::from_bytes(&buffer[64..96]);
+                    if has_attributes {
+                        new_item.set_attributes(&buffer[96..]);
+                    }
                     // Verify the MAC
                     buffer.clear();
                     buffer
-                        .extend_from_slice(comm.get(5 + 16, 5 + 16 + 96))
+                        .extend_from_slice(comm.get(5 + 16, 5 + 16 + len))
                         .unwrap();
                     unsafe {
                         tinyaes::AES_init_ctx_iv(
@@ -673,7 +1344,7 @@ fn import(
                             buffer.len() as u32,
                         );
                     }
-                    let received_mac = comm.get(5 + 16 + 96, 5 + 16 + 96 + 16);
+                    let received_mac = comm.get(5 + 16 + len, 5 + 16 + len + 16);
                     let expected_mac = &buffer[buffer.len() - 16..];
                     decrypt_failed = received_mac != expected_mac;
                 } else {
@@ -683,20 +1354,43 @@ fn import(
                     new_item.login = ArrayString::<32>::from_bytes(comm.get(offset, offset + 32));
                     offset += 32;
                     new_item.pass = ArrayString::<32>::from_bytes(comm.get(offset, offset + 32));
-                }
-                if !decrypt_failed {
-                    if let Some(index) = passwords.into_iter().position(|x| x.name == new_item.name)
-                    {
-                        passwords.remove(index);
+                    offset += 32;
+                    if has_attributes {
+                        new_item.set_attributes(comm.get(offset, offset + ATTRIBUTES_LEN));
                     }
-                    comm.reply::<Reply>(match passwords.add(&new_item) {
-                        Ok(()) => StatusWords::Ok.into(),
-                        Err(nvm::StorageFullError) => Error::StorageFull.into(),
-                    });
-                } else {
+                }
+                if decrypt_failed {
                     comm.reply(Error::DecryptFailed);
                     break;
                 }
+                let index = vault.position(&new_item.name);
+                if let Some(index) = index {
+                    // The protection level of the replaced entry is kept, and
+                    // replacing a protected entry requires consent.
+                    new_item.protection = vault.get(index).unwrap().protection();
+                    if matches!(
+                        new_item.protection,
+                        Protection::DoubleConfirm | Protection::DisplayOnly
+                    ) {
+                        if !ask_consent(
+                            &new_item.name,
+                            new_item.protection,
+                            &[&"Overwrite", &"password"],
+                        ) {
+                            comm.reply(Error::NoConsent);
+                            break;
+                        }
+                        ui::SingleMessage::new("Importing...").show();
+                    }
+                }
+                let result = match index {
+                    Some(index) => vault.replace(index, &new_item),
+                    None => vault.add(&new_item),
+                };
+                comm.reply::<Reply>(match result {
+                    Ok(()) => StatusWords::Ok.into(),
+                    Err(nvm::StorageFullError) => Error::StorageFull.into(),
+                });
             }
             _ => {
                 comm.reply(StatusWords::BadCla);
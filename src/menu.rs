@@ -0,0 +1,794 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! On-device menus, allowing to use the application without any client.
+
+use crate::audit::{Audit, Duplicates};
+use crate::keyboard::{self, LAYOUTS};
+use crate::password::{ArrayString, Protection, TAG_NAMES};
+#[cfg(not(target_os = "nanos"))]
+use crate::qr::QrCode;
+use crate::settings::Settings;
+use crate::storage::CAPACITY;
+use crate::strength;
+use crate::textinput::{TextInput, ALPHABET_PAGES, KEYBOARD_PAGES};
+use crate::vault::{Vault, MAX_VAULTS};
+use crate::{ask_consent, set_password, type_password, update_password, Error};
+use heapless::{String, Vec};
+use nanos_sdk::buttons::{ButtonEvent, ButtonsState};
+use nanos_sdk::nvm;
+use nanos_sdk::nvm::SingleStorage;
+use nanos_ui::ui;
+#[cfg(not(target_os = "nanos"))]
+use nanos_ui::{bagls, layout::Draw};
+
+/// Password ages after which a rotation reminder is shown, in days, selectable
+/// in the settings. Zero disables reminders.
+const ROTATION_AGES: [(u16, &str); 6] = [
+    (0, "Rotation: off"),
+    (30, "Rotation: 30 days"),
+    (60, "Rotation: 60 days"),
+    (90, "Rotation: 90 days"),
+    (180, "Rotation: 180 days"),
+    (365, "Rotation: 365 days"),
+];
+
+/// Number of password characters displayed on each page of `reveal`.
+const REVEAL_GROUP: usize = 4;
+
+/// Number of name characters kept to sort entries. The Nano S has little RAM,
+/// and its storage may hold up to `CAPACITY` entries.
+#[cfg(target_os = "nanos")]
+const SORT_PREFIX_LEN: usize = 2;
+#[cfg(not(target_os = "nanos"))]
+const SORT_PREFIX_LEN: usize = 6;
+
+/// Conversion to a two-digit number
+fn int2dec(x: usize) -> [u8; 2] {
+    let mut t = (x % 100) as u16;
+    if t == 0 {
+        return [b' ', b'0'];
+    }
+    let mut dec = [b' '; 2];
+    dec[1] = b'0' + (t as u8) % 10;
+    t /= 10;
+    if t != 0 {
+        dec[0] = b'0' + (t as u8) % 10;
+    }
+    dec
+}
+
+/// Display global information about the app:
+/// - Current number of passwords stored in the active vault
+/// - Number of passwords which need rotation, if any
+/// - App Version
+/// - Active vault
+///
+/// The menu also gives access to the stored entries, to the creation of new
+/// entries, to the reused passwords and to the settings.
+pub fn display_infos(vault: &mut Vault, settings: &mut nvm::AtomicStorage<Settings>) {
+    const APP_VERSION_STR: &str = concat!(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+    loop {
+        let mut stored_n = *b"   passwords";
+        let pwlen_bytes = int2dec(vault.len());
+
+        stored_n[0] = pwlen_bytes[0];
+        stored_n[1] = pwlen_bytes[1];
+
+        // safety: int2dec returns a [u8; 2] consisting of values between
+        // '0' and '9', thus is valid utf8
+        let stored_str = unsafe { core::str::from_utf8_unchecked(&stored_n) };
+
+        // Passwords older than the rotation age. This is only known once the
+        // host has set the time.
+        let stale = vault.stale(settings.get_ref().rotation_days).count();
+        let mut rotation_n = *b"   passwords need rotation";
+        let stale_bytes = int2dec(stale);
+        rotation_n[0] = stale_bytes[0];
+        rotation_n[1] = stale_bytes[1];
+        // safety: same as above
+        let rotation_str = unsafe { core::str::from_utf8_unchecked(&rotation_n) };
+
+        let mut items: Vec<&str, 9> = Vec::new();
+        items.push(APP_VERSION_STR).unwrap();
+        items.push(stored_str).unwrap();
+        if stale > 0 {
+            items.push(rotation_str).unwrap();
+        }
+        items
+            .extend_from_slice(&[
+                vault_name(settings.get_ref(), vault.id()),
+                "Browse",
+                "New password",
+                "Audit",
+                "Settings",
+                "Back",
+            ])
+            .unwrap();
+
+        let mut choice = ui::Menu::new(&items).show();
+        // The rotation line is informative, like the passwords count.
+        if stale > 0 && choice >= 2 {
+            choice -= 1;
+        }
+        match choice {
+            2 => vault_menu(vault, settings),
+            3 => browse(vault, settings.get_ref()),
+            4 => new_password(vault, settings.get_ref()),
+            5 => reused_passwords(vault, settings.get_ref()),
+            6 => settings_menu(settings),
+            _ => return,
+        }
+    }
+}
+
+/// Default names of the vaults, displayed until the user names them.
+const VAULT_NAMES: [&str; MAX_VAULTS] = ["Vault 1", "Vault 2", "Vault 3", "Vault 4"];
+
+/// Name of a vault, as displayed in the menu.
+fn vault_name(settings: &Settings, id: u8) -> &str {
+    let name = &settings.vault_names[id as usize];
+    match name.len() {
+        0 => VAULT_NAMES[id as usize],
+        _ => name.as_str(),
+    }
+}
+
+/// Lets the user select the active vault, or rename it. The selection and the
+/// names are saved in the settings, so the same vault is opened when the
+/// application restarts.
+fn vault_menu(vault: &mut Vault, settings: &mut nvm::AtomicStorage<Settings>) {
+    loop {
+        let current = *settings.get_ref();
+        let mut items = [""; MAX_VAULTS + 2];
+        for (id, item) in items[..MAX_VAULTS].iter_mut().enumerate() {
+            *item = vault_name(&current, id as u8);
+        }
+        items[MAX_VAULTS] = "Rename vault";
+        items[MAX_VAULTS + 1] = "Back";
+        match ui::Menu::new(&items).show() {
+            id if id < MAX_VAULTS => {
+                if id != vault.id() as usize {
+                    vault.switch(id as u8);
+                    let mut new = current;
+                    new.active_vault = id as u8;
+                    settings.update(&new);
+                }
+                return;
+            }
+            // The active vault is renamed. An empty name restores its default
+            // name.
+            id if id == MAX_VAULTS => {
+                let name = &current.vault_names[vault.id() as usize];
+                if let Some(name) = TextInput::new("Vault name", input_pages(&current)).ask(name) {
+                    let mut new = current;
+                    new.vault_names[vault.id() as usize] = name;
+                    settings.update(&new);
+                }
+            }
+            _ => return,
+        }
+    }
+}
+
+/// Character pages of the text input, according to the settings.
+fn input_pages(settings: &Settings) -> &'static [&'static str] {
+    match settings.qwerty_input {
+        true => &KEYBOARD_PAGES,
+        false => &ALPHABET_PAGES,
+    }
+}
+
+/// Sort key of an entry: characters of its name in lower case, padded with
+/// zeros, then its index in the vault.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct SortKey {
+    prefix: [u8; SORT_PREFIX_LEN],
+    index: u16,
+}
+
+impl SortKey {
+    /// Builds the sort key of an entry from the characters of its name
+    /// starting at `offset`.
+    fn new(name: &ArrayString<32>, offset: usize, index: usize) -> SortKey {
+        let mut key = SortKey {
+            prefix: [0; SORT_PREFIX_LEN],
+            index: index as u16,
+        };
+        for (k, c) in key.prefix.iter_mut().zip(&name.bytes()[offset..]) {
+            *k = c.to_ascii_lowercase();
+        }
+        key
+    }
+}
+
+/// Item selected in the list of entries.
+enum Selection {
+    Search,
+    Entry(usize),
+    Back,
+}
+
+/// Returns the sort keys of the entries of a folder whose name starts with
+/// the given prefix, sorted by name. Case is ignored for both filtering and
+/// sorting.
+///
+/// Only the first characters of names are kept to save RAM. Entries sharing
+/// these characters are then ordered by the following ones (see
+/// `sort_ties`).
+///
+/// # Arguments
+///
+/// * `prefix` - Name prefix.
+/// * `tags` - Tags mask of the folder. If zero, all entries are returned.
+fn sorted_entries(vault: &Vault, prefix: &str, tags: u8) -> Vec<SortKey, CAPACITY> {
+    let mut keys: Vec<SortKey, CAPACITY> = vault
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.matches_tags(tags))
+        .filter_map(|(i, p)| {
+            let name = p.name();
+            name.starts_with_ignore_case(prefix)
+                .then(|| SortKey::new(&name, 0, i))
+        })
+        .collect();
+    keys.sort_unstable();
+    sort_ties(vault, &mut keys, 0);
+    keys
+}
+
+/// Orders the runs of sorted keys sharing the same characters by the
+/// following characters of their names, until names differ or end.
+///
+/// The names of the entries of a run are decrypted once for each step of
+/// `SORT_PREFIX_LEN` characters, in a single pass over the vault, so that
+/// comparisons only use the keys.
+///
+/// # Arguments
+///
+/// * `keys` - Keys sorted by their characters starting at `offset`, then by
+///   index.
+/// * `offset` - Position in the names of the characters of the keys.
+fn sort_ties(vault: &Vault, keys: &mut [SortKey], offset: usize) {
+    let next = offset + SORT_PREFIX_LEN;
+    let mut start = 0;
+    while start < keys.len() {
+        let first = keys[start].prefix;
+        let end = start
+            + keys[start..]
+                .iter()
+                .take_while(|k| k.prefix == first)
+                .count();
+        // Shorter names are fully known from their characters.
+        if end - start > 1 && first[SORT_PREFIX_LEN - 1] != 0 && next < 32 {
+            let run = &mut keys[start..end];
+            // The keys of a run are sorted by index, as their characters are
+            // equal. Replacing these characters keeps this order.
+            for (i, p) in vault.iter().enumerate() {
+                if let Ok(pos) = run.binary_search_by_key(&(i as u16), |k| k.index) {
+                    run[pos] = SortKey::new(&p.name(), next, i);
+                }
+            }
+            run.sort_unstable();
+            sort_ties(vault, run, next);
+        }
+        start = end;
+    }
+}
+
+/// Lets the user scroll through the entries of a folder matching the search
+/// filter, sorted by name, and select one. The first item starts a search,
+/// and the last one goes back.
+///
+/// # Arguments
+///
+/// * `filter` - Only entries whose name starts with the filter are listed.
+/// * `tags` - Tags mask of the folder. If zero, all entries are listed.
+fn select_entry(vault: &Vault, filter: &ArrayString<32>, tags: u8) -> Selection {
+    let entries = sorted_entries(vault, filter.as_str(), tags);
+    let count = entries.len() + 2;
+    let mut buttons = ButtonsState::new();
+    // Start on the first entry, or on Back if there is none.
+    let mut index = 1;
+    let mut redraw = true;
+    loop {
+        if redraw {
+            match index {
+                0 if filter.len() == 0 => ui::SingleMessage::new("Search").show(),
+                0 => ui::Message::new("Search", filter.as_str()).show(),
+                i if i <= entries.len() => {
+                    let name = vault.get(entries[i - 1].index as usize).unwrap().name();
+                    ui::SingleMessage::new(name.as_str()).show()
+                }
+                _ => ui::SingleMessage::new("Back").show(),
+            }
+            redraw = false;
+        }
+        match ui::get_event(&mut buttons) {
+            Some(ButtonEvent::LeftButtonRelease) => {
+                index = (index + count - 1) % count;
+                redraw = true;
+            }
+            Some(ButtonEvent::RightButtonRelease) => {
+                index = (index + 1) % count;
+                redraw = true;
+            }
+            Some(ButtonEvent::BothButtonsRelease) => {
+                return match index {
+                    0 => Selection::Search,
+                    i if i <= entries.len() => Selection::Entry(entries[i - 1].index as usize),
+                    _ => Selection::Back,
+                };
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Incremental prefix search: the number of matching entries is updated as
+/// the user picks letters.
+/// Returns the new search filter, or None if the user cancels.
+fn search(
+    vault: &Vault,
+    filter: &ArrayString<32>,
+    tags: u8,
+    settings: &Settings,
+) -> Option<ArrayString<32>> {
+    TextInput::new("Search", input_pages(settings)).ask_with_count(filter, |prefix| {
+        Some(
+            vault
+                .iter()
+                .filter(|p| {
+                    p.matches_tags(tags) && p.name().starts_with_ignore_case(prefix.as_str())
+                })
+                .count(),
+        )
+    })
+}
+
+/// Browses the stored entries grouped by folder, and runs actions on the
+/// selected ones. Each tag is a folder, and entries with several tags appear
+/// in several folders.
+fn browse(vault: &mut Vault, settings: &Settings) {
+    let mut folders = ["All"; TAG_NAMES.len() + 2];
+    folders[1..=TAG_NAMES.len()].copy_from_slice(&TAG_NAMES);
+    folders[TAG_NAMES.len() + 1] = "Back";
+    loop {
+        let tags = match ui::Menu::new(&folders).show() {
+            0 => 0,
+            i if i <= TAG_NAMES.len() => 1 << (i - 1),
+            _ => return,
+        };
+        browse_folder(vault, tags, settings);
+    }
+}
+
+/// Browses the entries of a folder, and runs actions on the selected ones.
+///
+/// # Arguments
+///
+/// * `tags` - Tags mask of the folder. If zero, all entries are listed.
+fn browse_folder(vault: &mut Vault, tags: u8, settings: &Settings) {
+    let mut filter = ArrayString::<32>::new();
+    loop {
+        match select_entry(vault, &filter, tags) {
+            Selection::Search => {
+                if let Some(f) = search(vault, &filter, tags, settings) {
+                    filter = f;
+                }
+            }
+            Selection::Entry(index) => entry_menu(vault, index, settings),
+            Selection::Back => return,
+        }
+    }
+}
+
+/// Lists the entries sharing their password with other entries, grouped by
+/// password, and runs actions on the selected ones.
+fn reused_passwords(vault: &mut Vault, settings: &Settings) {
+    loop {
+        // Actions may change the passwords and the indexes of the entries, so
+        // the audit is run again each time.
+        ui::SingleMessage::new("Auditing...").show();
+        let duplicates = Audit::new(vault, strength::WEAK_BITS).duplicates();
+        if duplicates.is_empty() {
+            ui::popup("No reused password");
+            return;
+        }
+        match select_duplicate(vault, &duplicates) {
+            Some(index) => entry_menu(vault, index, settings),
+            None => return,
+        }
+    }
+}
+
+/// Lets the user scroll through the entries sharing their password, and
+/// select one. Each entry is displayed with the number of its group, entries
+/// of the same group having the same password. The last item goes back.
+fn select_duplicate(vault: &Vault, duplicates: &Duplicates) -> Option<usize> {
+    let count = duplicates.len() + 1;
+    let mut buttons = ButtonsState::new();
+    let mut index = 0;
+    let mut redraw = true;
+    loop {
+        if redraw {
+            match duplicates.iter().nth(index) {
+                Some((i, group)) => {
+                    let mut title = *b"Group   ";
+                    title[6..].copy_from_slice(&int2dec(group + 1));
+                    // safety: int2dec returns a valid utf8 [u8; 2]
+                    let title_str = unsafe { core::str::from_utf8_unchecked(&title) };
+                    let name = vault.get(i).unwrap().name();
+                    ui::Message::new(title_str, name.as_str()).show()
+                }
+                None => ui::SingleMessage::new("Back").show(),
+            }
+            redraw = false;
+        }
+        match ui::get_event(&mut buttons) {
+            Some(ButtonEvent::LeftButtonRelease) => {
+                index = (index + count - 1) % count;
+                redraw = true;
+            }
+            Some(ButtonEvent::RightButtonRelease) => {
+                index = (index + 1) % count;
+                redraw = true;
+            }
+            Some(ButtonEvent::BothButtonsRelease) => {
+                return duplicates.iter().nth(index).map(|(i, _)| i);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Short name of the class of a character. It is displayed below the
+/// character, so that ambiguous ones such as l, 1 and I can be told apart.
+fn char_class(c: u8) -> &'static str {
+    match c {
+        b'a'..=b'z' => "low",
+        b'A'..=b'Z' => "up",
+        b'0'..=b'9' => "dig",
+        b' ' => "spc",
+        _ if c.is_ascii_graphic() => "sym",
+        _ => "?",
+    }
+}
+
+/// Displays a password in groups of characters, one group per page, with the
+/// class of each character below it. Left and right buttons scroll through
+/// the groups, and both buttons exit.
+///
+/// Long passwords do not fit on the screen of the device, and are easier to
+/// type by hand this way.
+pub fn reveal(pass: &ArrayString<32>) {
+    let bytes = &pass.bytes()[..pass.len()];
+    if bytes.is_empty() {
+        ui::popup("Empty password");
+        return;
+    }
+    let pages = bytes.chunks(REVEAL_GROUP).len();
+    let mut buttons = ButtonsState::new();
+    let mut page = 0;
+    let mut redraw = true;
+    loop {
+        if redraw {
+            let group = bytes.chunks(REVEAL_GROUP).nth(page).unwrap();
+            let mut glyphs: String<{ 3 * REVEAL_GROUP }> = String::new();
+            let mut hints: String<{ 4 * REVEAL_GROUP }> = String::new();
+            for (i, &c) in group.iter().enumerate() {
+                if i > 0 {
+                    glyphs.push_str("  ").unwrap();
+                    hints.push(' ').unwrap();
+                }
+                glyphs
+                    .push(match c.is_ascii_graphic() || c == b' ' {
+                        true => c as char,
+                        false => '?',
+                    })
+                    .unwrap();
+                hints.push_str(char_class(c)).unwrap();
+            }
+            ui::Message::new(&glyphs, &hints).show();
+            redraw = false;
+        }
+        match ui::get_event(&mut buttons) {
+            Some(ButtonEvent::LeftButtonRelease) if page > 0 => {
+                page -= 1;
+                redraw = true;
+            }
+            Some(ButtonEvent::RightButtonRelease) if page + 1 < pages => {
+                page += 1;
+                redraw = true;
+            }
+            Some(ButtonEvent::BothButtonsRelease) => return,
+            _ => (),
+        }
+    }
+}
+
+/// Displays a password as a QR code, to be scanned with a phone camera.
+/// Both buttons exit. Returns false, without displaying anything, if the
+/// password cannot be encoded.
+///
+/// Modules are drawn as 2x2 pixels squares when the symbol and the 4 modules
+/// wide light margin readers need around it fit in height, and as single
+/// pixels otherwise. The rest of the screen is lit and serves as this margin.
+#[cfg(not(target_os = "nanos"))]
+pub fn show_qr(pass: &ArrayString<32>) -> bool {
+    let qr = match QrCode::encode(&pass.bytes()[..pass.len()]) {
+        Some(qr) => qr,
+        None => return false,
+    };
+    let scale = match (qr.size() + 8) * 2 <= nanos_ui::SCREEN_HEIGHT {
+        true => 2,
+        false => 1,
+    };
+    let side = qr.size() * scale;
+    let x0 = (nanos_ui::SCREEN_WIDTH - side) / 2;
+    let y0 = (nanos_ui::SCREEN_HEIGHT - side) / 2;
+    bagls::RectFull::new()
+        .pos(0, 0)
+        .width(nanos_ui::SCREEN_WIDTH as u32)
+        .height(nanos_ui::SCREEN_HEIGHT as u32)
+        .display();
+    // Dark modules are erased by horizontal runs, to limit the number of
+    // drawing calls.
+    for y in 0..qr.size() {
+        let mut x = 0;
+        while x < qr.size() {
+            if !qr.get(x, y) {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < qr.size() && qr.get(x, y) {
+                x += 1;
+            }
+            bagls::RectFull::new()
+                .pos((x0 + start * scale) as i32, (y0 + y * scale) as i32)
+                .width(((x - start) * scale) as u32)
+                .height(scale as u32)
+                .erase();
+        }
+    }
+    let mut buttons = ButtonsState::new();
+    while !matches!(
+        ui::get_event(&mut buttons),
+        Some(ButtonEvent::BothButtonsRelease)
+    ) {}
+    true
+}
+
+/// Lets the user select the tags of an entry.
+/// Returns the new tags mask, or None if the user cancels.
+///
+/// # Arguments
+///
+/// * `tags` - Current tags mask.
+fn edit_tags(mut tags: u8) -> Option<u8> {
+    // Tags, followed by Confirm and Cancel
+    let count = TAG_NAMES.len() + 2;
+    let mut buttons = ButtonsState::new();
+    let mut index = 0;
+    let mut redraw = true;
+    loop {
+        if redraw {
+            match index {
+                i if i < TAG_NAMES.len() => {
+                    let state = match tags & (1 << i) {
+                        0 => "No",
+                        _ => "Yes",
+                    };
+                    ui::Message::new(TAG_NAMES[i], state).show()
+                }
+                i if i == TAG_NAMES.len() => ui::SingleMessage::new("Confirm").show(),
+                _ => ui::SingleMessage::new("Cancel").show(),
+            }
+            redraw = false;
+        }
+        match ui::get_event(&mut buttons) {
+            Some(ButtonEvent::LeftButtonRelease) => {
+                index = (index + count - 1) % count;
+                redraw = true;
+            }
+            Some(ButtonEvent::RightButtonRelease) => {
+                index = (index + 1) % count;
+                redraw = true;
+            }
+            Some(ButtonEvent::BothButtonsRelease) => match index {
+                i if i < TAG_NAMES.len() => {
+                    tags ^= 1 << i;
+                    redraw = true;
+                }
+                i if i == TAG_NAMES.len() => return Some(tags),
+                _ => return None,
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Actions available for an entry: show, type, delete, regenerate, edit login
+/// or tags, and show as a QR code on devices with a large enough screen.
+///
+/// # Arguments
+///
+/// * `index` - Index of the entry in the vault.
+fn entry_menu(vault: &mut Vault, index: usize, settings: &Settings) {
+    let entry = vault.get(index).unwrap();
+    let (name, protection) = (entry.name(), entry.protection());
+    loop {
+        let mut items: Vec<&str, 9> = Vec::new();
+        items
+            .extend_from_slice(&[
+                name.as_str(),
+                "Show",
+                "Type",
+                "Delete",
+                "Regenerate",
+                "Edit login",
+                "Edit tags",
+            ])
+            .unwrap();
+        #[cfg(not(target_os = "nanos"))]
+        items.push("QR code").unwrap();
+        items.push("Back").unwrap();
+        match ui::Menu::new(&items).show() {
+            1 => {
+                if ask_consent(&name, protection, &[&"Read", &"password"]) {
+                    let item = vault.get(index).unwrap().decrypt();
+                    vault.record_access(index);
+                    ui::popup(item.login.as_str());
+                    reveal(&item.pass);
+                }
+            }
+            2 => {
+                let result = type_password(
+                    &vault.get(index).unwrap(),
+                    settings.type_options,
+                    settings.layout,
+                );
+                match result {
+                    Ok(()) => vault.record_access(index),
+                    Err(Error::DisplayOnly) => ui::popup("Display only"),
+                    Err(Error::CannotType) => ui::popup("Unsupported chars"),
+                    _ => (),
+                }
+            }
+            3 => {
+                if ask_consent(
+                    &name,
+                    protection.for_modification(),
+                    &[&"Remove", &"password"],
+                ) {
+                    vault.remove(index);
+                    return;
+                }
+            }
+            4 => {
+                // The entry is only decrypted once the user has consented.
+                if update_password(vault, &name, &None, &Some(None), None, None).is_ok() {
+                    return;
+                }
+            }
+            5 => {
+                // The current login is shown for editing, so the entry is only
+                // decrypted once the user has consented.
+                if !ask_consent(&name, protection, &[&"Read", &"login"]) {
+                    continue;
+                }
+                let current = vault.get(index).unwrap().decrypt().login;
+                let login = match TextInput::new("Login", input_pages(settings)).ask(&current) {
+                    Some(login) => login,
+                    None => continue,
+                };
+                // Only the login is changed, the password and its age are
+                // kept.
+                if update_password(vault, &name, &Some(login), &None, None, None).is_ok() {
+                    return;
+                }
+            }
+            6 => {
+                let tags = match edit_tags(vault.get(index).unwrap().tags()) {
+                    Some(tags) => tags,
+                    None => continue,
+                };
+                if update_password(vault, &name, &None, &None, None, Some(tags)).is_ok() {
+                    return;
+                }
+            }
+            #[cfg(not(target_os = "nanos"))]
+            7 => {
+                if ask_consent(&name, protection, &[&"Read", &"password"]) {
+                    let item = vault.get(index).unwrap().decrypt();
+                    if show_qr(&item.pass) {
+                        vault.record_access(index);
+                    } else {
+                        ui::popup("Cannot encode");
+                    }
+                }
+            }
+            _ => return,
+        }
+    }
+}
+
+/// Creates a new entry with a password generated by the device.
+/// Name and login are entered by the user.
+fn new_password(vault: &mut Vault, settings: &Settings) {
+    let input_name = TextInput::new("Name", input_pages(settings));
+    let name = match input_name.ask(&ArrayString::<32>::new()) {
+        Some(name) if name.len() > 0 => name,
+        _ => return,
+    };
+    let input_login = TextInput::new("Login", input_pages(settings));
+    let login = match input_login.ask(&ArrayString::<32>::new()) {
+        Some(login) => login,
+        None => return,
+    };
+    if let Err(Error::StorageFull) =
+        set_password(vault, &name, &login, &None, Protection::Confirm, 0)
+    {
+        ui::popup("Storage full");
+    }
+}
+
+/// Settings menu: keyboard layout, typing options and text input.
+fn settings_menu(settings: &mut nvm::AtomicStorage<Settings>) {
+    loop {
+        let mut new = *settings.get_ref();
+        let login_str = match new.type_options & keyboard::OPTION_LOGIN_TAB {
+            0 => "Type login: no",
+            _ => "Type login: yes",
+        };
+        let enter_str = match new.type_options & keyboard::OPTION_ENTER {
+            0 => "Press Enter: no",
+            _ => "Press Enter: yes",
+        };
+        let input_str = match new.qwerty_input {
+            false => "Input: ABC",
+            true => "Input: QWERTY",
+        };
+        let rotation_str = ROTATION_AGES
+            .iter()
+            .find(|(days, _)| *days == new.rotation_days)
+            .map_or("Rotation: custom", |(_, name)| *name);
+        match ui::Menu::new(&[
+            new.layout.name(),
+            login_str,
+            enter_str,
+            input_str,
+            rotation_str,
+            "Back",
+        ])
+        .show()
+        {
+            0 => {
+                let names = LAYOUTS.map(|l| l.name());
+                new.layout = LAYOUTS[ui::Menu::new(&names).show()];
+            }
+            1 => new.type_options ^= keyboard::OPTION_LOGIN_TAB,
+            2 => new.type_options ^= keyboard::OPTION_ENTER,
+            3 => new.qwerty_input = !new.qwerty_input,
+            4 => {
+                let names = ROTATION_AGES.map(|(_, name)| name);
+                new.rotation_days = ROTATION_AGES[ui::Menu::new(&names).show()].0;
+            }
+            _ => return,
+        }
+        settings.update(&new);
+    }
+}
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::convert::{TryFrom, TryInto};
+
 /// A basic class to store strings are fixed-size arrays.
 /// Storing null characters is not allowed (null is reserved to detect the
 /// end of the string). The stored string is not null terminated in the case
@@ -65,6 +67,39 @@ impl<const N: usize> ArrayString<N> {
         size
     }
 
+    /// Appends a character at the end of the string.
+    /// Returns false if the string is full.
+    pub fn push(&mut self, c: u8) -> bool {
+        let len = self.len();
+        if len == N {
+            return false;
+        }
+        self.bytes[len] = c;
+        true
+    }
+
+    /// Removes the last character of the string, if any. All the bytes of
+    /// a multi-byte UTF8 character are removed.
+    pub fn pop(&mut self) {
+        let mut len = self.len();
+        while len > 0 {
+            len -= 1;
+            let c = self.bytes[len];
+            self.bytes[len] = 0;
+            // Stop at the first byte of the character
+            if c & 0xc0 != 0x80 {
+                break;
+            }
+        }
+    }
+
+    /// Tells if the string starts with the given prefix, ignoring ASCII
+    /// case.
+    pub fn starts_with_ignore_case(&self, prefix: &str) -> bool {
+        let prefix = prefix.as_bytes();
+        prefix.len() <= self.len() && self.bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+    }
+
     /// Return the bytes, non-mutable!
     pub fn bytes(&self) -> &[u8; N] {
         &self.bytes
@@ -88,12 +123,85 @@ impl<const N: usize> core::cmp::PartialEq for ArrayString<N> {
 
 impl<const N: usize> Eq for ArrayString<N> {}
 
-/// Storage for a password.
+/// Access protection level of a password entry.
+///
+/// The level is checked by the device for every operation accessing the
+/// entry.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Protection {
+    /// User confirms once before each access. This is the default.
+    Confirm = 0,
+    /// No confirmation is required for reading. Meant for low-value entries.
+    NoConsent = 1,
+    /// User confirms twice, the name being displayed again on the second
+    /// screen.
+    DoubleConfirm = 2,
+    /// Password can only be displayed on the device screen and is never
+    /// returned to the host in plaintext.
+    DisplayOnly = 3,
+}
+
+impl TryFrom<u8> for Protection {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Self::Confirm),
+            1 => Ok(Self::NoConsent),
+            2 => Ok(Self::DoubleConfirm),
+            3 => Ok(Self::DisplayOnly),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Protection {
+    /// Protection level to be enforced when modifying or deleting an entry.
+    /// Entries which can be read without consent still require a
+    /// confirmation to be changed.
+    pub fn for_modification(self) -> Protection {
+        match self {
+            Protection::NoConsent => Protection::Confirm,
+            p => p,
+        }
+    }
+
+    /// Returns the stricter of two protection levels, as the one requiring
+    /// the most confirmations.
+    pub fn stricter(self, other: Protection) -> Protection {
+        let confirmations = |p| match p {
+            Protection::NoConsent => 0,
+            Protection::Confirm | Protection::DisplayOnly => 1,
+            Protection::DoubleConfirm => 2,
+        };
+        match confirmations(other) > confirmations(self) {
+            true => other,
+            false => self,
+        }
+    }
+
+    /// Name of the protection level, as displayed on confirmation screens.
+    pub fn label(self) -> &'static str {
+        match self {
+            Protection::Confirm => "confirm",
+            Protection::NoConsent => "no consent",
+            Protection::DoubleConfirm => "double confirm",
+            Protection::DisplayOnly => "display only",
+        }
+    }
+}
+
+/// Names of the tags an entry can be labeled with. Tags of an entry are
+/// stored as a bit mask, bit `i` corresponding to `TAG_NAMES[i]`.
+pub const TAG_NAMES: [&str; 8] = [
+    "Work", "Personal", "Infra", "Banking", "Social", "Shopping", "Dev", "Other",
+];
+
+/// A password entry, in plaintext.
 ///
-/// This is intended to be stored in the Flash memory:
-/// - members have fixed size
-/// - total size of PasswordItem should be a multiple of Flash page size (here
-///   64).
+/// Entries are only decrypted in RAM: the Flash memory stores them encrypted
+/// (see `vault::StoredItem`).
 ///
 /// As name and size are fixed arrays, we consider stored strings are padded
 /// with zeros. This is not null terminated, and UTF8 is allowed.
@@ -102,14 +210,95 @@ pub struct PasswordItem {
     pub name: ArrayString<32>,
     pub login: ArrayString<32>,
     pub pass: ArrayString<32>,
+    pub protection: Protection,
+    pub tags: u8,
+    /// Creation time, as a Unix timestamp supplied by the host. Zero if
+    /// unknown.
+    pub created: u32,
+    /// Time of the last modification, as a Unix timestamp supplied by the
+    /// host. Zero if unknown.
+    pub updated: u32,
+    /// Time of the last change of the password, as a Unix timestamp supplied
+    /// by the host. Zero if unknown.
+    pub changed: u32,
+    /// Number of times the password has been read.
+    pub accesses: u32,
+}
+
+/// Entry as stored by the versions prior to 1.4.0, in plaintext, in a slot of
+/// a `nvm::Collection`. Only used to convert these entries when the
+/// application is upgraded (see `vault::migrate_legacy`).
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct LegacyItem {
+    pub name: ArrayString<32>,
+    pub login: ArrayString<32>,
+    pub pass: ArrayString<32>,
 }
 
+impl LegacyItem {
+    pub const fn new() -> LegacyItem {
+        LegacyItem {
+            name: ArrayString::new(),
+            login: ArrayString::new(),
+            pass: ArrayString::new(),
+        }
+    }
+}
+
+/// Size of the attributes block appended to name, login and password during
+/// export and import.
+pub const ATTRIBUTES_LEN: usize = 32;
+
 impl PasswordItem {
     pub const fn new() -> PasswordItem {
         PasswordItem {
             name: ArrayString::new(),
             login: ArrayString::new(),
             pass: ArrayString::new(),
+            protection: Protection::Confirm,
+            tags: 0,
+            created: 0,
+            updated: 0,
+            changed: 0,
+            accesses: 0,
         }
     }
+
+    /// Returns the attributes block of the entry, as exported.
+    ///
+    /// The first byte is the protection level, the second one is the tags
+    /// mask. They are followed by two reserved bytes, then by the creation
+    /// time, modification time, access counter and password change time, each
+    /// encoded in big-endian with 4 bytes. Other bytes are reserved and set to
+    /// zero.
+    pub fn attributes(&self) -> [u8; ATTRIBUTES_LEN] {
+        let mut result = [0; ATTRIBUTES_LEN];
+        result[0] = self.protection as u8;
+        result[1] = self.tags;
+        result[4..8].copy_from_slice(&self.created.to_be_bytes());
+        result[8..12].copy_from_slice(&self.updated.to_be_bytes());
+        result[12..16].copy_from_slice(&self.accesses.to_be_bytes());
+        result[16..20].copy_from_slice(&self.changed.to_be_bytes());
+        result
+    }
+
+    /// Set the attributes of the entry from an exported attributes block.
+    /// Unknown protection levels fall back to the default level. If the
+    /// password change time is missing, the modification time is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Attributes block, `ATTRIBUTES_LEN` bytes long.
+    pub fn set_attributes(&mut self, bytes: &[u8]) {
+        self.protection = Protection::try_from(bytes[0]).unwrap_or(Protection::Confirm);
+        self.tags = bytes[1];
+        self.created = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+        self.updated = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
+        self.accesses = u32::from_be_bytes(bytes[12..16].try_into().unwrap());
+        self.changed = match u32::from_be_bytes(bytes[16..20].try_into().unwrap()) {
+            0 => self.updated,
+            changed => changed,
+        };
+    }
 }
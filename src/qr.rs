@@ -0,0 +1,249 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! QR code encoder (ISO/IEC 18004), for short payloads such as passwords.
+//!
+//! Only what is needed for payloads up to 42 bytes is implemented: byte mode,
+//! error correction level M, and versions 1 to 3, which have a single block of
+//! error correction codewords. The mask pattern is fixed: readers find it in
+//! the format information, and the penalty rules of the standard only aim at
+//! easing reading.
+
+/// Size of the largest supported symbol, in modules.
+const MAX_SIZE: usize = 29;
+
+/// Number of data and error correction codewords of each version, with error
+/// correction level M.
+const VERSIONS: [(usize, usize); 3] = [(16, 10), (28, 16), (44, 26)];
+
+/// Error correction level M, as encoded in the format information.
+const LEVEL_M: u32 = 0b00;
+
+/// Mask pattern 0: modules where (x + y) is even are inverted.
+const MASK: u32 = 0;
+
+/// Multiplication in GF(2^8), modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1d } else { 0 };
+        b >>= 1;
+    }
+    result
+}
+
+/// Computes the Reed-Solomon error correction codewords of data.
+///
+/// # Arguments
+///
+/// * `data` - Data codewords.
+/// * `ecc` - Receives the error correction codewords. Its length is the
+///   number of codewords to compute.
+fn reed_solomon(data: &[u8], ecc: &mut [u8]) {
+    // Generator polynomial, product of (x - 2^i) for i < degree. The leading
+    // coefficient is 1 and is omitted.
+    let degree = ecc.len();
+    let mut divisor = [0u8; 26];
+    let divisor = &mut divisor[..degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_mul(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_mul(root, 2);
+    }
+
+    // Remainder of the division of the data by the generator.
+    ecc.fill(0);
+    for &b in data {
+        let factor = b ^ ecc[0];
+        ecc.copy_within(1.., 0);
+        ecc[degree - 1] = 0;
+        for (e, &d) in ecc.iter_mut().zip(divisor.iter()) {
+            *e ^= gf_mul(d, factor);
+        }
+    }
+}
+
+/// A QR code symbol.
+pub struct QrCode {
+    /// Number of modules of each side.
+    size: usize,
+    /// Dark modules, one bit per module, bit `x` of row `y`.
+    modules: [u32; MAX_SIZE],
+    /// Function patterns, which do not hold data and are not masked.
+    function: [u32; MAX_SIZE],
+}
+
+impl QrCode {
+    /// Encodes a payload in the smallest symbol it fits in.
+    /// Returns None if the payload is longer than 42 bytes.
+    pub fn encode(data: &[u8]) -> Option<QrCode> {
+        let version = VERSIONS
+            .iter()
+            .position(|&(data_len, _)| data.len() + 2 <= data_len)?;
+        let (data_len, ecc_len) = VERSIONS[version];
+        let mut qr = QrCode {
+            size: 21 + 4 * version,
+            modules: [0; MAX_SIZE],
+            function: [0; MAX_SIZE],
+        };
+
+        // Byte mode indicator (4 bits) and character count (8 bits), then the
+        // data, shifted by a nibble. The terminator is made of zeros, and the
+        // remaining codewords are filled with the pad pattern.
+        let mut codewords = [0u8; 70];
+        codewords[0] = 0x40 | (data.len() as u8 >> 4);
+        let stream = core::iter::once(data.len() as u8).chain(data.iter().copied());
+        for (i, b) in stream.enumerate() {
+            codewords[i + 1] = (b << 4) | data.get(i).map_or(0, |d| d >> 4);
+        }
+        for (i, c) in codewords[data.len() + 2..data_len].iter_mut().enumerate() {
+            *c = [0xec, 0x11][i % 2];
+        }
+        let (data_codewords, ecc) = codewords.split_at_mut(data_len);
+        reed_solomon(data_codewords, &mut ecc[..ecc_len]);
+
+        qr.draw_function_patterns();
+        qr.draw_codewords(&codewords[..data_len + ecc_len]);
+        Some(qr)
+    }
+
+    /// Number of modules of each side.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Tells if the module at the given coordinates is dark.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        (self.modules[y] >> x) & 1 != 0
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y] = (self.modules[y] & !(1 << x)) | ((dark as u32) << x);
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.set(x, y, dark);
+        self.function[y] |= 1 << x;
+    }
+
+    fn is_function(&self, x: usize, y: usize) -> bool {
+        (self.function[y] >> x) & 1 != 0
+    }
+
+    /// Draws a square pattern centered on the given module, the color of each
+    /// module depending on its distance to the center. Modules out of the
+    /// symbol are skipped.
+    fn draw_square(&mut self, cx: usize, cy: usize, radius: isize, dark: fn(isize) -> bool) {
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (x, y) = (cx as isize + dx, cy as isize + dy);
+                if (0..self.size as isize).contains(&x) && (0..self.size as isize).contains(&y) {
+                    let dist = dx.abs().max(dy.abs());
+                    self.set_function(x as usize, y as usize, dark(dist));
+                }
+            }
+        }
+    }
+
+    /// Draws the finder, separator, timing and alignment patterns, the format
+    /// information and the dark module.
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns with their separators
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            self.draw_square(x, y, 4, |d| d != 2 && d != 4);
+        }
+
+        // Versions 2 and 3 have a single alignment pattern.
+        if size > 21 {
+            self.draw_square(size - 7, size - 7, 2, |d| d != 1);
+        }
+
+        // Format information: error correction level and mask pattern,
+        // protected by a BCH code.
+        let format = (LEVEL_M << 3) | MASK;
+        let mut rem = format;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = ((format << 10) | rem) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Places the codewords in zigzag, in pairs of columns from the right,
+    /// then applies the mask pattern.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            // The vertical timing pattern is skipped.
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for x in [right, right - 1] {
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    if !self.is_function(x, y) && i < codewords.len() * 8 {
+                        self.set(x, y, (codewords[i >> 3] >> (7 - (i & 7))) & 1 != 0);
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+
+        for y in 0..size {
+            for x in 0..size {
+                if !self.is_function(x, y) && (x + y) % 2 == 0 {
+                    self.set(x, y, !self.get(x, y));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::keyboard::Layout;
+use crate::password::ArrayString;
+use crate::vault::MAX_VAULTS;
+
+/// Application settings, changed from the device menu.
+///
+/// This is intended to be stored in the Flash memory, and updated atomically.
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// Keyboard layout of the host, used when typing passwords.
+    pub layout: Layout,
+    /// Typing options used from the device menu (see `keyboard::OPTION_*`).
+    pub type_options: u8,
+    /// Character pages of the text input follow a QWERTY keyboard instead of
+    /// the alphabetical order.
+    pub qwerty_input: bool,
+    /// Vault opened when the application starts (see `vault::Vault`).
+    pub active_vault: u8,
+    /// Names given to the vaults by the user. Vaults with an empty name are
+    /// displayed with a default name.
+    pub vault_names: [ArrayString<16>; MAX_VAULTS],
+    /// Number of days after which a password should be rotated. Zero
+    /// disables rotation reminders.
+    pub rotation_days: u16,
+}
+
+impl Settings {
+    pub const fn new() -> Settings {
+        Settings {
+            layout: Layout::Us,
+            type_options: 0,
+            qwerty_input: false,
+            active_vault: 0,
+            vault_names: [ArrayString::new(); MAX_VAULTS],
+            rotation_days: 90,
+        }
+    }
+}
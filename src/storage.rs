@@ -0,0 +1,425 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wear-aware storage of variable-size records in the Flash memory.
+//!
+//! The storage is divided in units of the size of a Flash page. Each unit has
+//! a header keeping its state and the number of times it has been written.
+//! There is no allocation table, which would be written on every change.
+//!
+//! A record is stored in one to `MAX_UNITS` units, so that small records do
+//! not waste space. The first unit of a record, the head, references the other
+//! ones. Units are allocated individually and do not need to be contiguous,
+//! so freed units are reused as they are and the storage never needs to be
+//! compacted. A unit is free if it is neither a head nor referenced by a head:
+//! removing a record only rewrites its head.
+//!
+//! New records are written to the least worn free units. Updated records are
+//! written to other units before the previous version is freed, so that
+//! entries updated frequently do not wear the same pages. If power is lost in
+//! between, both versions are found by `recover` which frees the old one.
+//!
+//! Each record also has a counter, kept apart from the records in pages of
+//! counters, so that it can be incremented in place without rewriting or
+//! moving the record.
+//!
+//! The storage has a header holding the version of the format of the records,
+//! so that records written by previous versions of the application can be
+//! detected and migrated.
+
+use heapless::Vec;
+use nanos_sdk::nvm::{self, AlignedStorage, AtomicStorage, SingleStorage};
+
+/// Number of units of the storage.
+///
+/// The default is enough for 128 records of the largest size. Devices with
+/// more Flash memory can store more entries, using the `storage-512` or
+/// `storage-1024` feature. A unit is 64 bytes long.
+#[cfg(feature = "storage-1024")]
+pub const CAPACITY: usize = 1024;
+#[cfg(all(feature = "storage-512", not(feature = "storage-1024")))]
+pub const CAPACITY: usize = 512;
+#[cfg(not(any(feature = "storage-512", feature = "storage-1024")))]
+pub const CAPACITY: usize = 384;
+
+/// Estimated number of write cycles a Flash page can sustain.
+pub const PAGE_ENDURANCE: u32 = 100_000;
+
+/// Size of the data of a unit, following its header.
+pub const UNIT_DATA_LEN: usize = 48;
+
+/// Maximum number of units of a record.
+pub const MAX_UNITS: usize = 3;
+
+/// Maximum size of a record.
+pub const MAX_RECORD_LEN: usize = UNIT_DATA_LEN * MAX_UNITS;
+
+/// Number of records of the largest size the storage can hold. Smaller
+/// records use fewer units, so more of them fit.
+pub const MAX_RECORDS: usize = CAPACITY / MAX_UNITS;
+
+const FREE: u8 = 0;
+const HEAD: u8 = 1;
+const PART: u8 = 2;
+
+/// Marks the absence of a replaced record.
+const NONE: u16 = u16::MAX;
+
+/// Number of record counters of a page of counters, one Flash page.
+const COUNTERS_PER_PAGE: usize = 32;
+
+/// Number of pages of counters, one counter per unit of the storage.
+const COUNTER_PAGES: usize = (CAPACITY + COUNTERS_PER_PAGE - 1) / COUNTERS_PER_PAGE;
+
+/// Number of words of a set of units, one bit per unit of the storage.
+const UNIT_SET_LEN: usize = (CAPACITY + 31) / 32;
+
+/// Header and data of a unit. The size is 64 bytes, one Flash page.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Unit {
+    state: u8,
+    /// Number of bytes of the record (head only)
+    len: u8,
+    /// Other units of the record (head only)
+    next: [u16; MAX_UNITS - 1],
+    /// Head of the previous version of the record, freed once this one is
+    /// written, or `NONE` (head only). The previous version is the only head
+    /// at this index with a lower sequence number, as the unit is reused only
+    /// once it has been freed.
+    replaces: u16,
+    /// Number of times the unit has been written
+    writes: u32,
+    /// Sequence number of the write which stored the unit. Each write uses a
+    /// new sequence number.
+    seq: u32,
+    data: [u8; UNIT_DATA_LEN],
+}
+
+impl Unit {
+    /// Number of units of the record starting at this head.
+    fn count(&self) -> usize {
+        units(self.len as usize)
+    }
+}
+
+/// Number of units used by a record of the given size.
+pub fn units(len: usize) -> usize {
+    ((len + UNIT_DATA_LEN - 1) / UNIT_DATA_LEN).max(1)
+}
+
+/// A record, read from the storage.
+pub type Record = Vec<u8, MAX_RECORD_LEN>;
+
+/// Usage of the storage.
+pub struct Stats {
+    /// Number of units holding records
+    pub used: usize,
+    /// Number of free units
+    pub free: usize,
+    /// Total number of units
+    pub capacity: usize,
+    /// Highest number of writes of a unit
+    pub max_writes: u32,
+    /// Estimated number of writes remaining before units wear out
+    pub remaining_writes: u32,
+}
+
+pub struct Storage<const N: usize> {
+    /// Version of the format of the records
+    version: AtomicStorage<u8>,
+    units: [AlignedStorage<Unit>; N],
+    /// Counter of each record, at the index of its head
+    counters: [AlignedStorage<[u16; COUNTERS_PER_PAGE]>; COUNTER_PAGES],
+}
+
+impl<const N: usize> Storage<N> {
+    /// Creates a storage with only free units.
+    ///
+    /// The version of the format of the records is zero, meaning unknown,
+    /// until the records have been checked and migrated (see
+    /// `vault::migrate`).
+    pub const fn new() -> Storage<N> {
+        assert!(N <= CAPACITY);
+        Storage {
+            version: AtomicStorage::new(&0),
+            units: [AlignedStorage::new(Unit {
+                state: FREE,
+                len: 0,
+                next: [0; MAX_UNITS - 1],
+                replaces: NONE,
+                writes: 0,
+                seq: 0,
+                data: [0; UNIT_DATA_LEN],
+            }); N],
+            counters: [AlignedStorage::new([0; COUNTERS_PER_PAGE]); COUNTER_PAGES],
+        }
+    }
+
+    /// Version of the format of the records, as recorded in the header.
+    pub fn version(&self) -> u8 {
+        *self.version.get_ref()
+    }
+
+    /// Records a new version of the format of the records, once they have
+    /// all been migrated.
+    pub fn set_version(&mut self, version: u8) {
+        self.version.update(&version);
+    }
+
+    fn unit(&self, index: usize) -> &Unit {
+        self.units[index].get_ref()
+    }
+
+    /// Iterates over the indexes of the heads.
+    fn heads(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..N).filter(move |&i| self.unit(i).state == HEAD)
+    }
+
+    /// Returns the units of the record starting at a head.
+    fn record_units(&self, head: usize) -> impl Iterator<Item = usize> + '_ {
+        let unit = self.unit(head);
+        core::iter::once(head).chain(unit.next[..unit.count() - 1].iter().map(|&i| i as usize))
+    }
+
+    /// Returns the set of the units holding a part of a record, built in a
+    /// single pass over the heads.
+    fn used_units(&self) -> [u32; UNIT_SET_LEN] {
+        let mut used = [0; UNIT_SET_LEN];
+        for i in self.heads().flat_map(|h| self.record_units(h)) {
+            used[i / 32] |= 1 << (i % 32);
+        }
+        used
+    }
+
+    /// Returns the highest sequence number of the units, from which the
+    /// sequence numbers of the writes of an operation follow.
+    fn last_seq(&self) -> u32 {
+        (0..N).map(|i| self.unit(i).seq).max().unwrap_or(0)
+    }
+
+    fn counter(&self, head: usize) -> u16 {
+        self.counters[head / COUNTERS_PER_PAGE].get_ref()[head % COUNTERS_PER_PAGE]
+    }
+
+    /// Writes the counter of a head, unless it already has this value.
+    fn set_counter(&mut self, head: usize, value: u16) {
+        let page = &mut self.counters[head / COUNTERS_PER_PAGE];
+        let mut counters = *page.get_ref();
+        if counters[head % COUNTERS_PER_PAGE] != value {
+            counters[head % COUNTERS_PER_PAGE] = value;
+            page.update(&counters);
+        }
+    }
+
+    /// Writes a unit, keeping track of its wear.
+    ///
+    /// # Arguments
+    ///
+    /// * `seq` - Sequence number of the previous write, incremented for this
+    ///   one (see `last_seq`).
+    fn write(&mut self, index: usize, mut unit: Unit, seq: &mut u32) {
+        *seq += 1;
+        unit.seq = *seq;
+        unit.writes = self.unit(index).writes.saturating_add(1);
+        self.units[index].update(&unit);
+    }
+
+    /// Frees a record by rewriting its head. The other units are freed as
+    /// they are not referenced anymore.
+    fn free(&mut self, head: usize, seq: &mut u32) {
+        let mut unit = *self.unit(head);
+        unit.state = FREE;
+        self.write(head, unit, seq);
+    }
+
+    /// Returns the least worn free units.
+    fn find_free_units(
+        &self,
+        count: usize,
+    ) -> Result<Vec<usize, MAX_UNITS>, nvm::StorageFullError> {
+        let mut used = self.used_units();
+        let mut free: Vec<usize, MAX_UNITS> = Vec::new();
+        for _ in 0..count {
+            let unit = (0..N)
+                .filter(|&i| used[i / 32] & (1 << (i % 32)) == 0)
+                .min_by_key(|&i| self.unit(i).writes)
+                .ok_or(nvm::StorageFullError)?;
+            used[unit / 32] |= 1 << (unit % 32);
+            free.push(unit).unwrap();
+        }
+        Ok(free)
+    }
+
+    /// Returns the head of a record.
+    fn index_to_head(&self, index: usize) -> Option<usize> {
+        self.heads().nth(index)
+    }
+
+    /// Writes a record, the head being written last. Its counter is written
+    /// before the head, so that the head never comes with the counter of a
+    /// record previously stored at its place.
+    fn write_record(
+        &mut self,
+        data: &[u8],
+        replaces: Option<usize>,
+        counter: u16,
+        seq: &mut u32,
+    ) -> Result<(), nvm::StorageFullError> {
+        let count = units(data.len());
+        let units = self.find_free_units(count)?;
+        let mut head = Unit {
+            state: HEAD,
+            len: data.len() as u8,
+            next: [0; MAX_UNITS - 1],
+            replaces: NONE,
+            writes: 0,
+            seq: 0,
+            data: [0; UNIT_DATA_LEN],
+        };
+        for (k, chunk) in data.chunks(UNIT_DATA_LEN).enumerate() {
+            let mut unit = head;
+            unit.data[..chunk.len()].copy_from_slice(chunk);
+            if k == 0 {
+                head = unit;
+            } else {
+                unit.state = PART;
+                head.next[k - 1] = units[k] as u16;
+                self.write(units[k], unit, seq);
+            }
+        }
+        if let Some(old) = replaces {
+            head.replaces = old as u16;
+        }
+        self.set_counter(units[0], counter);
+        self.write(units[0], head, seq);
+        Ok(())
+    }
+
+    /// Completes the updates interrupted by a power loss, by freeing the
+    /// previous versions of the records which have been written. Must be
+    /// called before the records are accessed.
+    pub fn recover(&mut self) {
+        let mut seq = self.last_seq();
+        for i in 0..N {
+            let unit = *self.unit(i);
+            if unit.state == HEAD && unit.replaces != NONE {
+                let old = unit.replaces as usize;
+                if self.unit(old).state == HEAD && self.unit(old).seq < unit.seq {
+                    self.free(old, &mut seq);
+                }
+            }
+        }
+    }
+
+    /// Reads a record.
+    fn read(&self, head: usize) -> Record {
+        let len = self.unit(head).len as usize;
+        let mut record = Record::new();
+        for i in self.record_units(head) {
+            let remaining = len - record.len();
+            let data = &self.unit(i).data;
+            record
+                .extend_from_slice(&data[..remaining.min(UNIT_DATA_LEN)])
+                .unwrap();
+        }
+        record
+    }
+
+    /// Iterates over the records.
+    pub fn iter(&self) -> impl Iterator<Item = Record> + '_ {
+        self.heads().map(move |h| self.read(h))
+    }
+
+    /// Iterates over the counters of the records, in the order of `iter`.
+    pub fn counters(&self) -> impl Iterator<Item = u16> + '_ {
+        self.heads().map(move |h| self.counter(h))
+    }
+
+    /// Increments the counter of a record, up to `u16::MAX`. Only the counter
+    /// is written, so the record keeps its index.
+    pub fn increment(&mut self, index: usize) {
+        if let Some(head) = self.index_to_head(index) {
+            self.set_counter(head, self.counter(head).saturating_add(1));
+        }
+    }
+
+    /// Number of records.
+    pub fn len(&self) -> usize {
+        self.heads().count()
+    }
+
+    /// Adds a record.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Record, up to `MAX_RECORD_LEN` bytes long.
+    /// * `counter` - Initial value of the counter of the record.
+    pub fn add(&mut self, data: &[u8], counter: u16) -> Result<(), nvm::StorageFullError> {
+        let mut seq = self.last_seq();
+        self.write_record(data, None, counter, &mut seq)
+    }
+
+    /// Removes a record.
+    pub fn remove(&mut self, index: usize) {
+        if let Some(head) = self.index_to_head(index) {
+            let mut seq = self.last_seq();
+            self.free(head, &mut seq);
+        }
+    }
+
+    /// Replaces a record by a new version, written to the least worn free
+    /// units.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the record.
+    /// * `data` - New version of the record, up to `MAX_RECORD_LEN` bytes
+    ///   long.
+    /// * `counter` - Counter of the new version. If None, the counter of the
+    ///   record is kept.
+    pub fn replace(
+        &mut self,
+        index: usize,
+        data: &[u8],
+        counter: Option<u16>,
+    ) -> Result<(), nvm::StorageFullError> {
+        match self.index_to_head(index) {
+            Some(old) => {
+                let counter = counter.unwrap_or_else(|| self.counter(old));
+                let mut seq = self.last_seq();
+                self.write_record(data, Some(old), counter, &mut seq)?;
+                self.free(old, &mut seq);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the usage of the storage and an estimate of its wear.
+    pub fn stats(&self) -> Stats {
+        let used = self.heads().map(|h| self.unit(h).count()).sum();
+        let max_writes = (0..N).map(|i| self.unit(i).writes).max().unwrap_or(0);
+        let remaining_writes = (0..N)
+            .map(|i| PAGE_ENDURANCE.saturating_sub(self.unit(i).writes))
+            .fold(0u32, |acc, r| acc.saturating_add(r));
+        Stats {
+            used,
+            free: N - used,
+            capacity: N,
+            max_writes,
+            remaining_writes,
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Password strength estimation.
+//!
+//! The estimate is the entropy of a random password of the same length, drawn
+//! from the character classes the password uses, lowered for the patterns
+//! attackers try first: repeated characters, sequences such as "abc" or
+//! "321", and common words. It runs in constant memory and without floating
+//! point arithmetic.
+
+/// Entropy below which a password is considered weak, in bits.
+pub const WEAK_BITS: u8 = 50;
+
+/// Words found in most password dictionaries, in lower case. A password
+/// containing one of them is estimated as if the word was a single character.
+const COMMON_WORDS: [&str; 14] = [
+    "password", "123456", "qwerty", "azerty", "letmein", "admin", "welcome", "iloveyou", "monkey",
+    "dragon", "abc123", "login", "master", "secret",
+];
+
+/// Number of possible characters for each character class.
+const LOWER_CHARS: u32 = 26;
+const UPPER_CHARS: u32 = 26;
+const DIGIT_CHARS: u32 = 10;
+const SYMBOL_CHARS: u32 = 33;
+
+/// Base 2 logarithm, in 1/16 of bits. The fractional part is linearly
+/// interpolated, which underestimates it slightly.
+fn log2_x16(n: u32) -> u32 {
+    if n == 0 {
+        return 0;
+    }
+    let k = 31 - n.leading_zeros();
+    16 * k + (((n - (1 << k)) * 16) >> k)
+}
+
+/// Estimates the entropy of a password, in bits.
+///
+/// # Arguments
+///
+/// * `pass` - Password, up to 32 bytes long.
+pub fn entropy(pass: &[u8]) -> u8 {
+    let pass = &pass[..pass.len().min(32)];
+
+    // Size of the alphabet the password is drawn from
+    let has = |f: fn(&u8) -> bool| pass.iter().any(f);
+    let pool: u32 = [
+        (has(u8::is_ascii_lowercase), LOWER_CHARS),
+        (has(u8::is_ascii_uppercase), UPPER_CHARS),
+        (has(u8::is_ascii_digit), DIGIT_CHARS),
+        (has(|c| !c.is_ascii_alphanumeric()), SYMBOL_CHARS),
+    ]
+    .iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, n)| n)
+    .sum();
+
+    // Weight of each character, in half characters. A character repeating or
+    // following the previous one only counts for half.
+    let mut weights = [2u8; 32];
+    for (i, pair) in pass.windows(2).enumerate() {
+        if pair[0].abs_diff(pair[1]) <= 1 {
+            weights[i + 1] = 1;
+        }
+    }
+
+    // A common word only counts for one character.
+    let mut lower = [0u8; 32];
+    lower[..pass.len()].copy_from_slice(pass);
+    lower.make_ascii_lowercase();
+    let lower = &lower[..pass.len()];
+    for word in COMMON_WORDS.iter().map(|w| w.as_bytes()) {
+        if let Some(p) = lower.windows(word.len()).position(|w| w == word) {
+            weights[p] = 2;
+            weights[p + 1..p + word.len()].fill(0);
+        }
+    }
+
+    let halves: u32 = weights[..pass.len()].iter().map(|&w| w as u32).sum();
+    (halves * log2_x16(pool) / 32).min(u8::MAX as u32) as u8
+}
+
+/// Tells if a password is weak.
+///
+/// # Arguments
+///
+/// * `pass` - Password, up to 32 bytes long.
+/// * `threshold` - Minimum entropy of a strong password, in bits.
+pub fn is_weak(pass: &[u8], threshold: u8) -> bool {
+    entropy(pass) < threshold
+}
@@ -0,0 +1,188 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Character picker, to enter text on the device with the two buttons.
+//!
+//! The left and right buttons scroll through the characters of the current
+//! page, followed by the Backspace, Next page, Confirm and Cancel actions.
+//! Pressing both buttons selects the displayed character or action.
+
+use crate::password::ArrayString;
+use heapless::Vec;
+use nanos_sdk::buttons::{ButtonEvent, ButtonsState};
+use nanos_ui::ui;
+
+/// Pages of characters, in alphabetical order.
+pub const ALPHABET_PAGES: [&str; 4] = [
+    "abcdefghijklmnopqrstuvwxyz",
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+    "0123456789",
+    " !\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~",
+];
+
+/// Pages of characters, following the rows of a QWERTY keyboard.
+pub const KEYBOARD_PAGES: [&str; 8] = [
+    "1234567890",
+    "qwertyuiop",
+    "asdfghjkl",
+    "zxcvbnm",
+    "QWERTYUIOP",
+    "ASDFGHJKL",
+    "ZXCVBNM",
+    " -_.@!?#$%&*+=/\\:;,'\"()[]{}<>^`|~",
+];
+
+/// Actions displayed after the characters of each page.
+const ACTIONS: [&str; 4] = ["Backspace", "Next page", "Confirm", "Cancel"];
+
+/// Maximum number of characters of the edited text displayed on screen.
+const MAX_VISIBLE: usize = 16;
+
+pub struct TextInput<'a> {
+    title: &'a str,
+    pages: &'a [&'a str],
+}
+
+impl<'a> TextInput<'a> {
+    /// Creates a character picker.
+    ///
+    /// # Arguments
+    ///
+    /// * `title` - Displayed instead of the text while it is empty.
+    /// * `pages` - Pages of ASCII characters the user can pick from.
+    pub fn new(title: &'a str, pages: &'a [&'a str]) -> TextInput<'a> {
+        TextInput { title, pages }
+    }
+
+    /// Lets the user edit a string.
+    /// Returns the new string, or None if the user cancels.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial` - Initial value of the string.
+    pub fn ask<const N: usize>(&self, initial: &ArrayString<N>) -> Option<ArrayString<N>> {
+        self.ask_with_count(initial, |_| None)
+    }
+
+    /// Lets the user edit a string, displaying a number next to it which is
+    /// updated each time the string changes. This is used for incremental
+    /// search, to display the number of matching entries.
+    /// Returns the new string, or None if the user cancels.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial` - Initial value of the string.
+    /// * `count` - Returns the number to be displayed for a given string.
+    pub fn ask_with_count<const N: usize, F>(
+        &self,
+        initial: &ArrayString<N>,
+        mut count: F,
+    ) -> Option<ArrayString<N>>
+    where
+        F: FnMut(&ArrayString<N>) -> Option<usize>,
+    {
+        let mut text = *initial;
+        let mut buttons = ButtonsState::new();
+        let mut page = 0;
+        let mut index = 0;
+        let mut redraw = true;
+        // Only updated when the text changes, to keep scrolling responsive.
+        let mut text_count = count(&text);
+        loop {
+            let chars = self.pages[page].as_bytes();
+            let items = chars.len() + ACTIONS.len();
+            if redraw {
+                self.draw(&text, text_count, chars, index);
+                redraw = false;
+            }
+            match ui::get_event(&mut buttons) {
+                Some(ButtonEvent::LeftButtonRelease) => {
+                    index = (index + items - 1) % items;
+                    redraw = true;
+                }
+                Some(ButtonEvent::RightButtonRelease) => {
+                    index = (index + 1) % items;
+                    redraw = true;
+                }
+                Some(ButtonEvent::BothButtonsRelease) => {
+                    match index.checked_sub(chars.len()) {
+                        None => {
+                            text.push(chars[index]);
+                            text_count = count(&text);
+                        }
+                        Some(0) => {
+                            text.pop();
+                            text_count = count(&text);
+                        }
+                        Some(1) => {
+                            page = (page + 1) % self.pages.len();
+                            index = 0;
+                        }
+                        Some(2) => return Some(text),
+                        _ => return None,
+                    }
+                    redraw = true;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Displays the end of the edited text followed by the optional count,
+    /// and the selected character or action below.
+    fn draw<const N: usize>(
+        &self,
+        text: &ArrayString<N>,
+        count: Option<usize>,
+        chars: &[u8],
+        index: usize,
+    ) {
+        let s = text.as_str();
+        let visible = match s.len() {
+            0 => self.title,
+            len => {
+                let start = s
+                    .char_indices()
+                    .map(|(i, _)| i)
+                    .find(|&i| len - i <= MAX_VISIBLE)
+                    .unwrap_or(len);
+                &s[start..]
+            }
+        };
+        let mut top_bytes: Vec<u8, { MAX_VISIBLE * 4 + 6 }> = Vec::new();
+        top_bytes.extend_from_slice(visible.as_bytes()).unwrap();
+        if let Some(n) = count {
+            // Formatted as " (n)", with n up to 3 digits.
+            let n = n.min(999);
+            top_bytes.extend_from_slice(b" (").unwrap();
+            if n >= 100 {
+                top_bytes.push(b'0' + (n / 100) as u8).unwrap();
+            }
+            if n >= 10 {
+                top_bytes.push(b'0' + (n / 10 % 10) as u8).unwrap();
+            }
+            top_bytes.push(b'0' + (n % 10) as u8).unwrap();
+            top_bytes.push(b')').unwrap();
+        }
+        // safety: built from a valid str and ASCII characters
+        let top = unsafe { core::str::from_utf8_unchecked(&top_bytes) };
+        let bottom = match chars.get(index) {
+            Some(b' ') => "Space",
+            // safety: pages only have ASCII characters
+            Some(c) => unsafe { core::str::from_utf8_unchecked(core::slice::from_ref(c)) },
+            None => ACTIONS[index - chars.len()],
+        };
+        ui::Message::new(top, bottom).show();
+    }
+}
@@ -0,0 +1,736 @@
+// Copyright 2020 Ledger SAS
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vaults are independent sets of entries sharing the same storage.
+//!
+//! Each entry belongs to one vault, and only the entries of the active vault
+//! can be accessed. Each vault has its own export encryption key, derived
+//! under a different SLIP16 sub-path.
+//!
+//! Entries are also bound to the seed they have been created with. When the
+//! device is unlocked with a temporary BIP39 passphrase, the seed is different
+//! and the entries created with the other seeds are neither listed nor
+//! accessible, and cannot be decrypted.
+//!
+//! Entries are encrypted at rest. Names are encrypted deterministically with a
+//! dedicated key, so they can be listed and looked up without decrypting
+//! logins and passwords, which are only decrypted in RAM once the operation
+//! has been authorized.
+
+use crate::password::{ArrayString, LegacyItem, PasswordItem, Protection};
+use crate::storage::{self, Record, Stats, Storage, CAPACITY};
+use crate::tinyaes;
+use core::convert::TryFrom;
+use core::mem::MaybeUninit;
+use nanos_sdk::ecc;
+use nanos_sdk::nvm;
+use nanos_sdk::random;
+
+/// Maximum number of vaults
+pub const MAX_VAULTS: usize = 4;
+
+/// SLIP16 path for password encryption (used during export/import)
+static BIP32_PATH: [u32; 2] = ecc::make_bip32_path(b"m/10016'/0");
+
+/// SLIP16 path used to identify the seed
+static SEED_ID_PATH: [u32; 2] = ecc::make_bip32_path(b"m/10016'/1");
+
+/// SLIP16 path for the encryption of stored logins and passwords
+static SECRET_KEY_PATH: [u32; 2] = ecc::make_bip32_path(b"m/10016'/2");
+
+/// SLIP16 path for the encryption of stored names
+static NAME_KEY_PATH: [u32; 2] = ecc::make_bip32_path(b"m/10016'/3");
+
+/// Size of the seed identifier stored in each entry. Entries of another seed
+/// are told apart with a probability of 1 - 2^-32, which is enough as they
+/// could not be decrypted anyway.
+pub const SEED_ID_LEN: usize = 4;
+
+/// Derives a key from the seed.
+///
+/// The application exits if the derivation fails: going on with a zero key
+/// would encrypt entries, and identify the seed, with a key anyone knows.
+fn derive(path: &[u32]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    if ecc::bip32_derive(ecc::CurvesId::Secp256k1, path, &mut key).is_err() {
+        panic!("key derivation failed");
+    }
+    key
+}
+
+/// Derives a key specific to a vault, using the hardened child `id` of a
+/// path.
+fn derive_vault_key(path: &[u32; 2], id: u8) -> [u8; 32] {
+    derive(&[path[0], path[1], 0x8000_0000 | id as u32])
+}
+
+/// Computes the identifier of the current seed.
+///
+/// The identifier is a part of a key derived on a dedicated path, so it does
+/// not reveal anything about the keys used for encryption.
+fn seed_id() -> [u8; SEED_ID_LEN] {
+    let key = derive(&SEED_ID_PATH);
+    let mut id = [0u8; SEED_ID_LEN];
+    id.copy_from_slice(&key[..SEED_ID_LEN]);
+    id
+}
+
+/// Derives the encryption key of a vault.
+///
+/// The first vault uses the SLIP16 path itself, so exports made before vaults
+/// were introduced can still be imported. Vault `i` uses the hardened child
+/// `i` of this path.
+fn derive_key(id: u8) -> [u8; 32] {
+    match id {
+        0 => derive(&BIP32_PATH),
+        _ => derive_vault_key(&BIP32_PATH, id),
+    }
+}
+
+/// Keys used to encrypt the entries of a vault in the Flash memory.
+struct StorageKeys {
+    /// Key for names, used with a fixed IV so that a name always has the same
+    /// encrypted form.
+    name: [u8; 32],
+    /// Key for logins and passwords, used with a random nonce for each entry.
+    secret: [u8; 32],
+}
+
+impl StorageKeys {
+    fn derive(id: u8) -> StorageKeys {
+        StorageKeys {
+            name: derive_vault_key(&NAME_KEY_PATH, id),
+            secret: derive_vault_key(&SECRET_KEY_PATH, id),
+        }
+    }
+
+    /// Encrypts a name in AES-256-CBC with a zero IV. The name is padded with
+    /// zeros to one or two blocks.
+    /// Returns the buffer and the length of the encrypted name.
+    fn encrypt_name(&self, name: &ArrayString<32>) -> ([u8; 32], usize) {
+        let mut buffer = [0u8; 32];
+        buffer.copy_from_slice(name.bytes());
+        let len = match name.len() {
+            0..=16 => 16,
+            _ => 32,
+        };
+        let mut aes_ctx = MaybeUninit::<tinyaes::AES_ctx>::uninit();
+        unsafe {
+            tinyaes::AES_init_ctx_iv(aes_ctx.as_mut_ptr(), self.name.as_ptr(), [0u8; 16].as_ptr());
+            tinyaes::AES_CBC_encrypt_buffer(aes_ctx.as_mut_ptr(), buffer.as_mut_ptr(), len as u32);
+        }
+        (buffer, len)
+    }
+
+    fn decrypt_name(&self, encrypted: &[u8]) -> ArrayString<32> {
+        let mut buffer = [0u8; 32];
+        buffer[..encrypted.len()].copy_from_slice(encrypted);
+        let mut aes_ctx = MaybeUninit::<tinyaes::AES_ctx>::uninit();
+        unsafe {
+            tinyaes::AES_init_ctx_iv(aes_ctx.as_mut_ptr(), self.name.as_ptr(), [0u8; 16].as_ptr());
+            tinyaes::AES_CBC_decrypt_buffer(
+                aes_ctx.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                encrypted.len() as u32,
+            );
+        }
+        ArrayString::from_bytes(&buffer)
+    }
+
+    /// Encrypts or decrypts login and password in AES-256-CTR.
+    fn xcrypt_secret(&self, nonce: &[u8; NONCE_LEN], buffer: &mut [u8]) {
+        let mut iv = [0u8; 16];
+        iv[..NONCE_LEN].copy_from_slice(nonce);
+        let mut aes_ctx = MaybeUninit::<tinyaes::AES_ctx>::uninit();
+        unsafe {
+            tinyaes::AES_init_ctx_iv(aes_ctx.as_mut_ptr(), self.secret.as_ptr(), iv.as_ptr());
+            tinyaes::AES_CTR_xcrypt_buffer(
+                aes_ctx.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+            );
+        }
+    }
+}
+
+/// Size of the nonce used to encrypt logins and passwords. The remaining bytes
+/// of the CTR IV are used by the counter.
+const NONCE_LEN: usize = 8;
+
+/// Version of the format of the records, stored as their first byte and in
+/// the header of the storage.
+///
+/// Versions have the high bit set, so that they cannot be mistaken for the
+/// first byte of the records written before the format was versioned, which
+/// is a protection level.
+///
+/// When the format changes, this must be increased and the conversion from
+/// the previous format added to `upgrade_record`.
+pub const RECORD_VERSION: u8 = 0x81;
+
+/// Number of slots of the storage of the versions prior to 1.4.0.
+pub const LEGACY_CAPACITY: usize = 128;
+
+/// Storage of the versions prior to 1.4.0: one entry per slot, in plaintext,
+/// without vaults, protection levels nor tags.
+pub type LegacyStorage = nvm::Collection<LegacyItem, LEGACY_CAPACITY>;
+
+/// Size of the format version, of the plaintext members of a stored entry and
+/// of the length of the encrypted secret.
+const HEADER_LEN: usize = 4 + SEED_ID_LEN + NONCE_LEN + 6;
+
+/// Bits of the flags byte of a record, packing the protection level, the
+/// vault identifier and the length of the encrypted name.
+const FLAGS_PROTECTION: u8 = 0x03;
+const FLAGS_VAULT_SHIFT: u8 = 2;
+const FLAGS_VAULT: u8 = 0x0c;
+const FLAGS_LONG_NAME: u8 = 0x10;
+
+/// Number of seconds in a day. Times are stored as a number of days since the
+/// Unix epoch.
+const DAY: u32 = 86400;
+
+/// Maximum size of the encrypted login and password. They are separated by a
+/// null byte, and padded with zeros to a multiple of 8 bytes so that their
+/// exact length is not revealed.
+const MAX_SECRET_LEN: usize = 72;
+
+/// Entry as stored in the Flash memory.
+///
+/// Name, login and password are encrypted with the storage keys of the vault.
+/// Other members are stored in plaintext, as they are needed to list entries
+/// without user consent.
+///
+/// Entries are packed in records of variable size (see `storage::Storage`):
+/// the format version, the flags (see `FLAGS_*`), the tags, the length of the
+/// encrypted secret, the other plaintext members, then the encrypted name and
+/// secret. The smallest entries fit in a single storage unit.
+#[derive(Clone, Copy)]
+pub struct StoredItem {
+    /// Encrypted name, 16 or 32 bytes long
+    name: [u8; 32],
+    name_len: usize,
+    /// Encrypted login and password
+    secret: [u8; MAX_SECRET_LEN],
+    secret_len: usize,
+    /// Nonce used to encrypt the login and password
+    nonce: [u8; NONCE_LEN],
+    protection: Protection,
+    tags: u8,
+    /// Identifier of the vault the entry belongs to
+    vault: u8,
+    /// Identifier of the seed the entry has been created with
+    seed: [u8; SEED_ID_LEN],
+    /// Creation day
+    created: u16,
+    /// Day of the last modification
+    updated: u16,
+    /// Day of the last change of the password
+    changed: u16,
+    /// Number of times the password has been read, stored apart from the
+    /// record (see `Storage::increment`)
+    accesses: u16,
+}
+
+impl StoredItem {
+    fn new() -> StoredItem {
+        StoredItem {
+            name: [0; 32],
+            name_len: 0,
+            secret: [0; MAX_SECRET_LEN],
+            secret_len: 0,
+            nonce: [0; NONCE_LEN],
+            protection: Protection::Confirm,
+            tags: 0,
+            vault: 0,
+            seed: [0; SEED_ID_LEN],
+            created: 0,
+            updated: 0,
+            changed: 0,
+            accesses: 0,
+        }
+    }
+
+    /// Size of the record of the entry.
+    fn record_len(&self) -> usize {
+        HEADER_LEN + self.name_len + self.secret_len
+    }
+
+    /// Packs the entry in a record.
+    fn to_record(&self) -> Record {
+        let mut record = Record::new();
+        let mut flags = self.protection as u8 | (self.vault << FLAGS_VAULT_SHIFT);
+        if self.name_len > 16 {
+            flags |= FLAGS_LONG_NAME;
+        }
+        record
+            .extend_from_slice(&[RECORD_VERSION, flags, self.tags, self.secret_len as u8])
+            .unwrap();
+        record.extend_from_slice(&self.seed).unwrap();
+        record.extend_from_slice(&self.nonce).unwrap();
+        record
+            .extend_from_slice(&self.created.to_be_bytes())
+            .unwrap();
+        record
+            .extend_from_slice(&self.updated.to_be_bytes())
+            .unwrap();
+        record
+            .extend_from_slice(&self.changed.to_be_bytes())
+            .unwrap();
+        record
+            .extend_from_slice(&self.name[..self.name_len])
+            .unwrap();
+        record
+            .extend_from_slice(&self.secret[..self.secret_len])
+            .unwrap();
+        record
+    }
+
+    /// Unpacks an entry from a record.
+    /// Returns None if the record is invalid, or has not been migrated to the
+    /// current format.
+    fn from_record(record: &[u8]) -> Option<StoredItem> {
+        if record.len() < HEADER_LEN || record[0] != RECORD_VERSION {
+            return None;
+        }
+        let (header, data) = record.split_at(HEADER_LEN);
+        let mut item = StoredItem::new();
+        let flags = header[1];
+        item.protection =
+            Protection::try_from(flags & FLAGS_PROTECTION).unwrap_or(Protection::Confirm);
+        item.vault = (flags & FLAGS_VAULT) >> FLAGS_VAULT_SHIFT;
+        item.name_len = match flags & FLAGS_LONG_NAME {
+            0 => 16,
+            _ => 32,
+        };
+        item.tags = header[2];
+        item.secret_len = header[3] as usize;
+        let mut offset = 4;
+        item.seed
+            .copy_from_slice(&header[offset..offset + SEED_ID_LEN]);
+        offset += SEED_ID_LEN;
+        item.nonce
+            .copy_from_slice(&header[offset..offset + NONCE_LEN]);
+        offset += NONCE_LEN;
+        let u16_at = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+        item.created = u16_at(offset);
+        item.updated = u16_at(offset + 2);
+        item.changed = u16_at(offset + 4);
+        if item.secret_len > MAX_SECRET_LEN || data.len() != item.name_len + item.secret_len {
+            return None;
+        }
+        item.name[..item.name_len].copy_from_slice(&data[..item.name_len]);
+        item.secret[..item.secret_len].copy_from_slice(&data[item.name_len..]);
+        Some(item)
+    }
+}
+
+/// Entry of a vault, read from the Flash memory.
+///
+/// Name and metadata can be read freely. Login and password are decrypted by
+/// `decrypt`, which must only be called once the user has consented to the
+/// operation.
+pub struct Entry<'a> {
+    item: StoredItem,
+    keys: &'a StorageKeys,
+}
+
+impl<'a> Entry<'a> {
+    /// Decrypted name of the entry.
+    pub fn name(&self) -> ArrayString<32> {
+        self.keys
+            .decrypt_name(&self.item.name[..self.item.name_len])
+    }
+
+    pub fn protection(&self) -> Protection {
+        self.item.protection
+    }
+
+    pub fn tags(&self) -> u8 {
+        self.item.tags
+    }
+
+    /// Tells if the entry has at least one of the given tags. An empty mask
+    /// matches all entries.
+    pub fn matches_tags(&self, tags: u8) -> bool {
+        tags == 0 || (self.item.tags & tags) != 0
+    }
+
+    /// Creation time, as a Unix timestamp rounded down to the day. Zero if
+    /// unknown.
+    pub fn created(&self) -> u32 {
+        self.item.created as u32 * DAY
+    }
+
+    /// Time of the last modification, as a Unix timestamp rounded down to the
+    /// day. Zero if unknown.
+    pub fn updated(&self) -> u32 {
+        self.item.updated as u32 * DAY
+    }
+
+    /// Number of times the password has been read.
+    pub fn accesses(&self) -> u32 {
+        self.item.accesses as u32
+    }
+
+    /// Time of the last change of the password, as a Unix timestamp rounded
+    /// down to the day. Zero if unknown.
+    pub fn changed(&self) -> u32 {
+        self.item.changed as u32 * DAY
+    }
+
+    /// Tells if the password has not been changed for at least the given
+    /// number of days. Other modifications, such as a new login or new tags,
+    /// are not taken into account. Entries of unknown age are never reported.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - Current time, as a Unix timestamp.
+    /// * `days` - Maximum age of the password.
+    pub fn is_older_than(&self, now: u32, days: u16) -> bool {
+        self.item.changed != 0 && now / DAY >= self.item.changed as u32 + days as u32
+    }
+
+    /// Tells if the entry has the given encrypted name.
+    fn has_name(&self, encrypted: &([u8; 32], usize)) -> bool {
+        self.item.name[..self.item.name_len] == encrypted.0[..encrypted.1]
+    }
+
+    /// Decrypts the whole entry in RAM.
+    pub fn decrypt(&self) -> PasswordItem {
+        let mut secret = self.item.secret;
+        let secret = &mut secret[..self.item.secret_len];
+        self.keys.xcrypt_secret(&self.item.nonce, secret);
+        // Login and password are separated by a null byte.
+        let separator = secret.iter().position(|&c| c == 0).unwrap_or(secret.len());
+        let (login, pass) = secret.split_at(separator);
+        let pass = pass.get(1..).unwrap_or(&[]);
+        let mut result = PasswordItem::new();
+        result.name = self.name();
+        result.login = ArrayString::from_bytes(login);
+        result.pass = ArrayString::from_bytes(&pass[..pass.len().min(32)]);
+        result.protection = self.item.protection;
+        result.tags = self.item.tags;
+        result.created = self.created();
+        result.updated = self.updated();
+        result.changed = self.changed();
+        result.accesses = self.accesses();
+        result
+    }
+}
+
+/// Converts a record written by a previous version of the application to the
+/// current format.
+/// Returns None if the record is already in the current format, or cannot be
+/// converted.
+///
+/// Entries of all vaults and seeds are migrated at once, so conversions must
+/// not depend on the encryption keys.
+fn upgrade_record(_record: &[u8]) -> Option<Record> {
+    // The current format is the first one, there is nothing to convert yet.
+    None
+}
+
+/// Migrates the records written by previous versions of the application to
+/// the current format. Must be called when the application starts, after
+/// `Storage::recover` and before any entry is accessed.
+///
+/// Each record is replaced by its new version in a power-loss safe way (see
+/// `Storage::replace`), and only the records not yet migrated are converted.
+/// If the migration is interrupted, it resumes at the next start.
+pub fn migrate(passwords: &mut Storage<CAPACITY>) {
+    if passwords.version() >= RECORD_VERSION {
+        return;
+    }
+    // Replaced records move in the storage, so the search restarts after
+    // each conversion.
+    while let Some((index, record)) = passwords
+        .iter()
+        .enumerate()
+        .find_map(|(i, r)| Some((i, upgrade_record(&r)?)))
+    {
+        if passwords.replace(index, &record, None).is_err() {
+            // Not enough space: the migration will be retried at the next
+            // start.
+            return;
+        }
+    }
+    passwords.set_version(RECORD_VERSION);
+}
+
+/// Converts the entries of the versions prior to 1.4.0 to records of the
+/// first vault of the current seed, with the default protection level. Must
+/// be called when the application starts, after `migrate`.
+///
+/// Each entry is added to the storage before being removed from the legacy
+/// storage. If the conversion is interrupted, it resumes at the next start,
+/// and an entry which has already been added is not added again.
+pub fn migrate_legacy(legacy: &mut LegacyStorage, passwords: &mut Storage<CAPACITY>) {
+    if legacy.len() == 0 {
+        return;
+    }
+    let mut vault = Vault::open(passwords, 0);
+    while let Some(&old) = legacy.into_iter().next() {
+        if vault.position(&old.name).is_none() {
+            let mut item = PasswordItem::new();
+            item.name = old.name;
+            item.login = old.login;
+            item.pass = old.pass;
+            if vault.add(&item).is_err() {
+                // Not enough space: the conversion will be retried at the
+                // next start.
+                return;
+            }
+        }
+        legacy.remove(0);
+    }
+}
+
+/// Access counter of an entry, as stored.
+fn accesses(item: &PasswordItem) -> u16 {
+    item.accesses.min(u16::MAX as u32) as u16
+}
+
+/// View of the entries of the active vault.
+///
+/// Indexes used by the methods are relative to the entries of the vault.
+pub struct Vault<'a> {
+    passwords: &'a mut Storage<CAPACITY>,
+    id: u8,
+    seed: [u8; SEED_ID_LEN],
+    key: [u8; 32],
+    storage_keys: StorageKeys,
+    /// Current time supplied by the host, as a Unix timestamp. Zero if
+    /// unknown.
+    now: u32,
+}
+
+impl<'a> Vault<'a> {
+    /// Opens a vault of the current seed, deriving its encryption keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `passwords` - Storage of the entries of all vaults.
+    /// * `id` - Vault identifier, lower than `MAX_VAULTS`.
+    pub fn open(passwords: &'a mut Storage<CAPACITY>, id: u8) -> Vault<'a> {
+        Vault {
+            passwords,
+            id,
+            seed: seed_id(),
+            key: derive_key(id),
+            storage_keys: StorageKeys::derive(id),
+            now: 0,
+        }
+    }
+
+    /// Makes another vault the active one.
+    pub fn switch(&mut self, id: u8) {
+        self.id = id;
+        self.key = derive_key(id);
+        self.storage_keys = StorageKeys::derive(id);
+    }
+
+    /// Identifier of the vault.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Current time, as last supplied by the host. Zero if unknown.
+    pub fn now(&self) -> u32 {
+        self.now
+    }
+
+    /// Sets the current time. The device has no clock, so it must be
+    /// supplied by the host.
+    pub fn set_time(&mut self, now: u32) {
+        self.now = now;
+    }
+
+    /// Encryption key of the vault, used for export and import.
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Tells if a stored entry belongs to the vault.
+    fn contains(&self, item: &StoredItem) -> bool {
+        item.vault == self.id && item.seed == self.seed
+    }
+
+    /// Iterates over the entries of the vault, with their index in the
+    /// storage. Invalid records are skipped.
+    fn items(&self) -> impl Iterator<Item = (usize, StoredItem)> + '_ {
+        self.passwords
+            .iter()
+            .zip(self.passwords.counters())
+            .enumerate()
+            .filter_map(|(i, (record, accesses))| {
+                let mut item = StoredItem::from_record(&record)?;
+                item.accesses = accesses;
+                Some((i, item))
+            })
+            .filter(move |(_, item)| self.contains(item))
+    }
+
+    /// Iterates over the entries of the vault.
+    pub fn iter(&self) -> impl Iterator<Item = Entry<'_>> + '_ {
+        self.items().map(move |(_, item)| Entry {
+            item,
+            keys: &self.storage_keys,
+        })
+    }
+
+    /// Number of entries in the vault.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Iterates over the entries which need rotation, their password not
+    /// having been changed for at least the given number of days.
+    ///
+    /// Nothing is returned if the current time is unknown, or if `days` is
+    /// zero, rotation being disabled.
+    pub fn stale(&self, days: u16) -> impl Iterator<Item = Entry<'_>> + '_ {
+        let now = self.now;
+        self.iter()
+            .filter(move |p| now != 0 && days != 0 && p.is_older_than(now, days))
+    }
+
+    /// Returns the entry at the given index.
+    pub fn get(&self, index: usize) -> Option<Entry<'_>> {
+        self.iter().nth(index)
+    }
+
+    /// Returns the index of the entry with the given name.
+    ///
+    /// The name is encrypted and compared to the stored names, so that no
+    /// entry has to be decrypted.
+    pub fn position(&self, name: &ArrayString<32>) -> Option<usize> {
+        let encrypted = self.storage_keys.encrypt_name(name);
+        self.iter().position(|p| p.has_name(&encrypted))
+    }
+
+    /// Returns the entry with the given name.
+    pub fn find(&self, name: &ArrayString<32>) -> Option<Entry<'_>> {
+        let encrypted = self.storage_keys.encrypt_name(name);
+        self.iter().find(|p| p.has_name(&encrypted))
+    }
+
+    /// Encrypts an entry and packs it in a record.
+    fn seal(&self, item: &PasswordItem) -> Record {
+        let mut stored = StoredItem::new();
+        (stored.name, stored.name_len) = self.storage_keys.encrypt_name(&item.name);
+        random::rand_bytes(&mut stored.nonce);
+        let login = &item.login.bytes()[..item.login.len()];
+        let pass = &item.pass.bytes()[..item.pass.len()];
+        stored.secret[..login.len()].copy_from_slice(login);
+        stored.secret[login.len() + 1..login.len() + 1 + pass.len()].copy_from_slice(pass);
+        stored.secret_len = (login.len() + 1 + pass.len() + 7) / 8 * 8;
+        self.storage_keys
+            .xcrypt_secret(&stored.nonce, &mut stored.secret[..stored.secret_len]);
+        stored.protection = item.protection;
+        stored.tags = item.tags;
+        stored.created = (item.created / DAY) as u16;
+        stored.updated = (item.updated / DAY) as u16;
+        stored.changed = (item.changed / DAY) as u16;
+        stored.vault = self.id;
+        stored.seed = self.seed;
+        stored.to_record()
+    }
+
+    /// Converts the index of an entry of the vault to its index in the
+    /// storage.
+    fn storage_index(&self, index: usize) -> Option<usize> {
+        self.items().nth(index).map(|(i, _)| i)
+    }
+
+    /// Encrypts an entry and adds it to the vault.
+    pub fn add(&mut self, item: &PasswordItem) -> Result<(), nvm::StorageFullError> {
+        let record = self.seal(item);
+        self.passwords.add(&record, accesses(item))
+    }
+
+    /// Replaces the entry at the given index.
+    pub fn replace(
+        &mut self,
+        index: usize,
+        item: &PasswordItem,
+    ) -> Result<(), nvm::StorageFullError> {
+        let record = self.seal(item);
+        match self.storage_index(index) {
+            Some(i) => self.passwords.replace(i, &record, Some(accesses(item))),
+            None => Ok(()),
+        }
+    }
+
+    /// Increments the access counter of the entry at the given index.
+    ///
+    /// The counter is updated in place: the entry is neither decrypted nor
+    /// rewritten, so it keeps its index.
+    pub fn record_access(&mut self, index: usize) {
+        if let Some(i) = self.storage_index(index) {
+            self.passwords.increment(i);
+        }
+    }
+
+    /// Changes the name of the entry at the given index.
+    ///
+    /// Only the name is encrypted again: the login and password are left as
+    /// they are. The entry is rewritten atomically.
+    pub fn rename(
+        &mut self,
+        index: usize,
+        name: &ArrayString<32>,
+    ) -> Result<(), nvm::StorageFullError> {
+        match self.items().nth(index) {
+            Some((i, mut item)) => {
+                (item.name, item.name_len) = self.storage_keys.encrypt_name(name);
+                if self.now != 0 {
+                    item.updated = (self.now / DAY) as u16;
+                }
+                self.passwords.replace(i, &item.to_record(), None)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the entry at the given index.
+    pub fn remove(&mut self, index: usize) {
+        if let Some(i) = self.storage_index(index) {
+            self.passwords.remove(i);
+        }
+    }
+
+    /// Removes all the entries of the vault. Other vaults, including the
+    /// vaults of other seeds, are not modified.
+    pub fn clear(&mut self) {
+        for index in (0..self.len()).rev() {
+            self.remove(index);
+        }
+    }
+
+    /// Usage of the storage by the entries of the vault, and wear of the
+    /// whole storage.
+    ///
+    /// Units used by other vaults and seeds are counted as free, so that the
+    /// existence of their entries is not revealed.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.passwords.stats();
+        stats.used = self
+            .items()
+            .map(|(_, item)| storage::units(item.record_len()))
+            .sum();
+        stats.free = stats.capacity - stats.used;
+        stats
+    }
+}
@@ -21,12 +21,16 @@ import socket
 import os.path
 from speculos.client import SpeculosClient
 
+APP_PATH = os.path.join(os.path.dirname(__file__), "..", "target",
+    "thumbv6m-none-eabi", "release", "nanopass")
+
 class Automaton(SpeculosClient):
-    def __init__(self):
-        app_path = os.path.join(
-            os.path.dirname(__file__), "..", "target", "thumbv6m-none-eabi",
-            "release", "nanopass")
-        super().__init__(app_path)
+    def __init__(self, app_path: str = APP_PATH, args: list = []):
+        """
+        :param app_path: Application to be run by speculos.
+        :param args: Additional arguments of speculos.
+        """
+        super().__init__(app_path, args=args)
         self.actions = ''
         self.cla = 0x80
 
@@ -54,6 +58,18 @@ class Automaton(SpeculosClient):
                     # Next actions for next APDU
                     break;
             return response.receive()
+
+    def navigate(self, actions: str):
+        """
+        Press buttons outside of any APDU, to go through the menus of the
+        device. Actions are given as in self.actions.
+        """
+        press = {'r': self.press_right, 'l': self.press_left,
+            'b': self.press_both}
+        for c in actions:
+            sleep(0.1)
+            press[c]()
+        sleep(0.1)
         
     def press(self, button: str):
         self.press_and_release(button)
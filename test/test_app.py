@@ -14,9 +14,19 @@
 # See the License for the specific language governing permissions and
 # limitations under the License.
 
-from automaton import Automaton
-from nanopass import Client
+from automaton import Automaton, APP_PATH
+from nanopass import (Client, PROTECTION_CONFIRM, PROTECTION_NO_CONSENT,
+    PROTECTION_DOUBLE_CONFIRM, PROTECTION_DISPLAY_ONLY,
+    AUDIT_WEAK, AUDIT_REUSED, ATTRIBUTES_LEN, MAX_NAME_LEN, str_to_bytes_pad)
+from elftools.elf.elffile import ELFFile
+import binascii
+import hashlib
+import json
+import os.path
+import pytest
 import random
+import shutil
+import subprocess
 
 def subtest_password_list(client, passwords):
     """ Test password name listing. """
@@ -47,7 +57,340 @@ def subtest_clear(client, auto, passwords):
     client.clear()
     assert client.get_size() == 0
 
-def test_app():
+def subtest_protection(client, auto):
+    """ Test entry protection levels. """
+    size = client.get_size()
+    auto.actions = "rb"
+    client.add("low", "a", "b", PROTECTION_NO_CONSENT)
+    # No user action required
+    assert client.get_by_name("low") == ("a", "b")
+    auto.actions = "rb"
+    client.add("display", "c", "d", PROTECTION_DISPLAY_ONLY)
+    with pytest.raises(Exception):
+        client.get_by_name("display")
+
+    # Importing an entry with the same name does not lower the protection,
+    # and replacing a display-only entry requires consent
+    attributes = bytes([PROTECTION_NO_CONSENT]) + bytes(ATTRIBUTES_LEN - 1)
+    entry = b"".join(str_to_bytes_pad(s, MAX_NAME_LEN)
+        for s in ("display", "e", "f")) + attributes
+    auto.actions = ";b;rb"
+    client.import_("1.3.0", [entry], encrypted=False)
+    with pytest.raises(Exception):
+        client.get_by_name("display")
+
+    for name in ("low", "display"):
+        auto.actions = "rb"
+        client.delete_by_name(name)
+    assert client.get_size() == size
+
+def subtest_tags(client, auto):
+    """ Test entry tags and filtered listing. """
+    size = client.get_size()
+    entries = [("t1", 0x01), ("t2", 0x02), ("t3", 0x03)]
+    for name, tags in entries:
+        auto.actions = "rb"
+        client.add(name, "", "x", tags=tags)
+    assert set(client.get_names(0x01)) == {"t1", "t3"}
+    assert set(client.get_names(0x02)) == {"t2", "t3"}
+    assert client.get_size(0x04) == 0
+    assert client.get_size() == size + len(entries)
+    for name, _ in entries:
+        auto.actions = "rb"
+        client.delete_by_name(name)
+
+def subtest_storage_stats(client, auto):
+    """ Test storage usage reporting, and spreading of updates. """
+    stats = client.get_storage_stats()
+    # Each entry uses at least one storage unit
+    assert stats['used'] >= client.get_size()
+    assert stats['used'] + stats['free'] == stats['capacity']
+    assert client.get_capacity() >= 128
+    auto.actions = "rb"
+    client.add("wear", "", "1")
+    # Updating the entry writes it to another slot
+    auto.actions = "rb"
+    client.add("wear", "", "2")
+    auto.actions = "rb"
+    assert client.get_by_name("wear") == ("", "2")
+    stats2 = client.get_storage_stats()
+    assert stats2['used'] > stats['used']
+    assert stats2['remaining_writes'] < stats['remaining_writes']
+    auto.actions = "rb"
+    client.delete_by_name("wear")
+    assert client.get_storage_stats()['used'] == stats['used']
+
+def subtest_metadata(client, auto):
+    """ Test entry timestamps and access counter. """
+    day = 86400
+    created = 1700000000
+    client.set_time(created)
+    auto.actions = "rb"
+    client.add("meta", "", "1")
+    metadata = client.get_metadata("meta")
+    assert metadata == {'created': created // day * day,
+        'updated': created // day * day, 'accesses': 0,
+        'changed': created // day * day}
+    for i in range(2):
+        auto.actions = "rb"
+        client.get_by_name("meta")
+    assert client.get_metadata("meta")['accesses'] == 2
+    # Updating the entry keeps its creation time and usage
+    client.set_time(created + 10 * day)
+    auto.actions = "rb"
+    client.add("meta", "", "2")
+    metadata = client.get_metadata("meta")
+    assert metadata['created'] == created // day * day
+    assert metadata['updated'] == (created // day + 10) * day
+    assert metadata['changed'] == (created // day + 10) * day
+    assert metadata['accesses'] == 2
+    # The password change time is kept when other fields are changed
+    client.set_time(created + 20 * day)
+    auto.actions = "rbrb"
+    client.update("meta", login="alice", tags=0x01)
+    metadata = client.get_metadata("meta")
+    assert metadata['updated'] == (created // day + 20) * day
+    assert metadata['changed'] == (created // day + 10) * day
+    with pytest.raises(Exception):
+        client.get_metadata("undefined")
+    auto.actions = "rb"
+    client.delete_by_name("meta")
+
+def subtest_rotation(client, auto):
+    """ Test listing of entries which need rotation. """
+    day = 86400
+    # Default rotation age is 90 days
+    now = 1700000000
+    client.set_time(now)
+    auto.actions = "rb"
+    client.add("rotate", "", "1")
+    client.set_time(now + 89 * day)
+    assert client.get_stale_names() == []
+    auto.actions = "rb"
+    client.add("fresh", "", "1")
+    client.set_time(now + 90 * day)
+    assert client.get_stale_names() == ["rotate"]
+    assert client.get_stale_names(0x01) == []
+    # Renaming or changing other fields does not reset the age
+    auto.actions = "rbrb"
+    client.rename("rotate", "rotated")
+    auto.actions = "rbrb"
+    client.rename("rotated", "rotate")
+    auto.actions = "rb"
+    client.update("rotate", login="alice")
+    assert client.get_stale_names() == ["rotate"]
+    # Updating the password resets its age
+    auto.actions = "rb"
+    client.add("rotate", "", "2")
+    assert client.get_stale_names() == []
+    for name in ("rotate", "fresh"):
+        auto.actions = "rb"
+        client.delete_by_name(name)
+
+def subtest_audit(client, auto):
+    """ Test weak and reused password detection. """
+    entries = [("audit weak", "Password1"), ("audit strong", "Ahd9ooquai3eeNgu"),
+        ("audit reused", "Ahd9ooquai3eeNgu")]
+    for name, password in entries:
+        auto.actions = "rb"
+        client.add(name, "", password)
+    auto.actions = "b"
+    findings = client.audit()
+    assert findings["audit weak"] == AUDIT_WEAK
+    assert findings["audit strong"] == AUDIT_REUSED
+    assert findings["audit reused"] == AUDIT_REUSED
+    # A lower threshold accepts the weak password
+    auto.actions = "b"
+    assert "audit weak" not in client.audit(threshold=10)
+    auto.actions = "rb"
+    with pytest.raises(Exception):
+        client.audit()
+    # Display-only entries are not audited, whatever the threshold
+    auto.actions = "rb"
+    client.add("audit display", "", "Password1", PROTECTION_DISPLAY_ONLY)
+    auto.actions = "b"
+    assert "audit display" not in client.audit(threshold=255)
+    auto.actions = "rb"
+    client.delete_by_name("audit display")
+    for name, _ in entries:
+        auto.actions = "rb"
+        client.delete_by_name(name)
+
+def subtest_duplicates(client, auto):
+    """ Test reused password listing. """
+    entries = [("dup a1", "Ahd9ooquai3eeNgu"), ("dup b1", "kei8Shoh"),
+        ("dup a2", "Ahd9ooquai3eeNgu"), ("dup b2", "kei8Shoh"),
+        ("dup a3", "Ahd9ooquai3eeNgu"), ("dup c", "Iu0eiw2f")]
+    for name, password in entries:
+        auto.actions = "rb"
+        client.add(name, "", password)
+    auto.actions = "b"
+    groups = client.get_duplicates()
+    assert (sorted(sorted(g) for g in groups) ==
+        [["dup a1", "dup a2", "dup a3"], ["dup b1", "dup b2"]])
+    auto.actions = "rb"
+    with pytest.raises(Exception):
+        client.get_duplicates()
+    for name, _ in entries:
+        auto.actions = "rb"
+        client.delete_by_name(name)
+    auto.actions = "b"
+    assert client.get_duplicates() == []
+
+    # A display-only entry sharing the password of a readable one is not
+    # reported, as the host could then read its password
+    auto.actions = "rb"
+    client.add("dup display", "", "Ahd9ooquai3eeNgu", PROTECTION_DISPLAY_ONLY)
+    auto.actions = "rb"
+    client.add("dup readable", "", "Ahd9ooquai3eeNgu")
+    auto.actions = "b"
+    assert client.get_duplicates() == []
+    for name in ("dup display", "dup readable"):
+        auto.actions = "rb"
+        client.delete_by_name(name)
+
+def subtest_breach(client, auto):
+    """ Test breach check, with a local stand-in of the range API. """
+    breached = ["Password1", "letmein", "ahN8ohPh"]
+    hashes = [hashlib.sha1(p.encode()).hexdigest().upper() for p in breached]
+    prefixes = []
+    def range_api(prefix):
+        prefixes.append(prefix)
+        # Many unrelated suffixes, so that hashes are sent in several chunks
+        suffixes = [f"{i:035X}" for i in range(30)]
+        return suffixes + [h[5:] for h in hashes if h.startswith(prefix)]
+    # The result is only displayed on the device
+    for name, password in [("breach yes", "ahN8ohPh"),
+            ("breach no", "Ahd9ooquai3eeNgu")]:
+        auto.actions = "rb"
+        client.add(name, "", password)
+        auto.actions = "rb;;;b"
+        client.check_breach(name, range_api)
+        assert prefixes[-1] == hashlib.sha1(password.encode()).hexdigest()[:5].upper()
+        auto.actions = "rb"
+        client.delete_by_name(name)
+
+    # Display-only entries are refused without asking for consent
+    auto.actions = "rb"
+    client.add("breach display", "", "ahN8ohPh", PROTECTION_DISPLAY_ONLY)
+    with pytest.raises(Exception):
+        client.check_breach("breach display", range_api)
+    auto.actions = "rb"
+    client.delete_by_name("breach display")
+
+def subtest_rename(client, auto):
+    """ Test entry renaming. """
+    auto.actions = "rb"
+    client.add("before", "login", "pass")
+    auto.actions = "rb"
+    client.add("other", "", "x")
+    auto.actions = "rb"
+    client.get_by_name("before")
+    # Existing and missing names are rejected without user interaction
+    with pytest.raises(Exception):
+        client.rename("before", "other")
+    with pytest.raises(Exception):
+        client.rename("undefined", "after")
+    # Both names must be confirmed
+    auto.actions = "rbrrb"
+    with pytest.raises(Exception):
+        client.rename("before", "after")
+    auto.actions = "rbrb"
+    client.rename("before", "after")
+    assert not client.has_name("before")
+    auto.actions = "rb"
+    assert client.get_by_name("after") == ("login", "pass")
+    assert client.get_metadata("after")['accesses'] == 2
+    for name in ("after", "other"):
+        auto.actions = "rb"
+        client.delete_by_name(name)
+
+def subtest_update(client, auto):
+    """ Test partial updates of entries. """
+    auto.actions = "rb"
+    client.add("partial", "login", "pass", tags=0x01)
+    auto.actions = "rb"
+    client.update("partial", login="login2")
+    auto.actions = "rb"
+    assert client.get_by_name("partial") == ("login2", "pass")
+    auto.actions = "rb"
+    client.update("partial", password="pass2")
+    auto.actions = "rb"
+    assert client.get_by_name("partial") == ("login2", "pass2")
+    auto.actions = "rb"
+    client.update("partial", generate=True)
+    auto.actions = "rb"
+    login, password = client.get_by_name("partial")
+    assert login == "login2" and len(password) == 16
+    # Each field is confirmed on its own screen
+    auto.actions = "rbrb"
+    client.update("partial", protection=PROTECTION_NO_CONSENT, tags=0x02)
+    assert client.get_names(0x01) == []
+    assert client.get_names(0x02) == ["partial"]
+    assert client.get_by_name("partial") == (login, password)
+    auto.actions = "rbrrb"
+    with pytest.raises(Exception):
+        client.update("partial", login="x", password="y")
+    assert client.get_by_name("partial") == (login, password)
+    with pytest.raises(Exception):
+        client.update("undefined", login="x")
+    # Unknown fields, and a password both supplied and generated, are rejected
+    data = str_to_bytes_pad("partial", MAX_NAME_LEN)
+    with pytest.raises(Exception):
+        client.dev.apdu_exchange(0x1a, data, p1=0x20)
+    with pytest.raises(Exception):
+        client.dev.apdu_exchange(0x1a, data + bytes(32), p1=0x06)
+    auto.actions = "rb"
+    client.delete_by_name("partial")
+
+    # Lowering a double confirmation requires both confirmations
+    auto.actions = "rb"
+    client.add("double", "", "pass", PROTECTION_DOUBLE_CONFIRM)
+    auto.actions = "rbrrb"
+    with pytest.raises(Exception):
+        client.update("double", protection=PROTECTION_CONFIRM)
+    auto.actions = "rbrb"
+    client.update("double", protection=PROTECTION_CONFIRM)
+    auto.actions = "rb"
+    assert client.get_by_name("double") == ("", "pass")
+    auto.actions = "rb"
+    client.delete_by_name("double")
+
+    # The protection of display-only entries cannot be lowered by the host
+    auto.actions = "rb"
+    client.add("display", "", "pass", PROTECTION_DISPLAY_ONLY)
+    with pytest.raises(Exception):
+        client.update("display", protection=PROTECTION_NO_CONSENT)
+    with pytest.raises(Exception):
+        client.get_by_name("display")
+    auto.actions = "rb"
+    client.delete_by_name("display")
+
+def subtest_recover(client, tmp_path, export_encrypted, export_plain):
+    """ Decrypt an export with the host recovery tool and the default seed of
+    Speculos. """
+    if shutil.which("cargo") is None:
+        # The host tools cannot be built
+        return
+    mnemonic = ("glory promote mansion idle axis finger extra february uncover "
+        "one trip resource lawn turtle enact monster seven myth punch hobby "
+        "comfort wild raise skin")
+    encrypted = tmp_path / "encrypted.json"
+    plaintext = tmp_path / "plaintext.json"
+    encrypted.write_text(json.dumps({
+        'version': client.get_version(),
+        'encrypted': True,
+        'entries': [binascii.hexlify(e).decode() for e in export_encrypted]
+    }))
+    manifest = os.path.join(os.path.dirname(__file__), "..", "host",
+        "Cargo.toml")
+    subprocess.run(["cargo", "run", "--quiet", "--manifest-path", manifest,
+        "-p", "nanopass-recover", "--", str(encrypted), str(plaintext)],
+        input=mnemonic.encode(), check=True)
+    entries = json.loads(plaintext.read_text())['entries']
+    assert entries == [binascii.hexlify(e).decode() for e in export_plain]
+
+def test_app(tmp_path):
     passwords = [
         ("x", "", "1"),
         ("want", "a", "epuu7Aeja9"),
@@ -76,6 +419,7 @@ def test_app():
     export_plain = client.export(encrypt=False)
     auto.actions = "b"
     export_encrypted = client.export()
+    subtest_recover(client, tmp_path, export_encrypted, export_plain)
 
     # Test password removal
     removal_order = [name for name, _, _ in passwords]
@@ -91,14 +435,156 @@ def test_app():
     # Test import plain
     subtest_clear(client, auto, passwords)
     auto.actions = ";b"
-    client.import_("1.1.0", export_plain, encrypted=False)
+    client.import_("1.3.0", export_plain, encrypted=False)
+    subtest_password_list(client, passwords)
+    subtest_password_retrieval(client, auto, passwords)
+
+    # Test import plain, from a version without attributes
+    subtest_clear(client, auto, passwords)
+    auto.actions = ";b"
+    client.import_("1.1.0", [p[:96] for p in export_plain], encrypted=False)
     subtest_password_list(client, passwords)
     subtest_password_retrieval(client, auto, passwords)
 
     # Test import encrypted
     subtest_clear(client, auto, passwords)
     auto.actions = ";b"
-    client.import_("1.1.0", export_encrypted, encrypted=True)
+    client.import_("1.3.0", export_encrypted, encrypted=True)
     subtest_password_list(client, passwords)
     subtest_password_retrieval(client, auto, passwords)
 
+    subtest_protection(client, auto)
+    subtest_tags(client, auto)
+    subtest_storage_stats(client, auto)
+    subtest_metadata(client, auto)
+    subtest_rotation(client, auto)
+    subtest_audit(client, auto)
+    subtest_duplicates(client, auto)
+    subtest_breach(client, auto)
+    subtest_rename(client, auto)
+    subtest_update(client, auto)
+    auto.stop()
+
+def write_legacy_app(path, passwords):
+    """
+    Write a copy of the application with entries stored as versions prior to
+    1.4.0 did, in the `nvm::Collection<PasswordItem, 128>` of the SDK: an
+    `AtomicStorage` of the allocation flags, made of two copies each preceded
+    by its validity flag, then the 128 slots. All members are aligned on 64
+    bytes.
+    """
+    shutil.copy(APP_PATH, path)
+    with open(path, 'r+b') as f:
+        elf = ELFFile(f)
+        symbol = next(s for s in elf.get_section_by_name('.symtab').iter_symbols()
+            if '9PASSWORDS' in s.name)
+        address = symbol['st_value']
+        section = next(s for s in elf.iter_sections()
+            if s['sh_addr'] <= address < s['sh_addr'] + s['sh_size'])
+        offset = section['sh_offset'] + address - section['sh_addr']
+        flags = bytes([1] * len(passwords)).ljust(128, b'\0')
+        f.seek(offset + 64)
+        f.write(flags)
+        for i, (name, login, password) in enumerate(passwords):
+            f.seek(offset + 384 + i * 128)
+            f.write(b''.join(s.encode().ljust(32, b'\0')
+                for s in (name, login, password)))
+
+def test_upgrade(tmp_path):
+    """ Test the conversion of the entries stored by versions prior to 1.4.0. """
+    passwords = [
+        ("want", "a", "epuu7Aeja9"),
+        ("quick fiber estate ripple phrase", "topic", "huu4aeju2gooth1iS6ai")
+    ]
+    app_path = tmp_path / "nanopass"
+    write_legacy_app(app_path, passwords)
+    with Automaton(str(app_path)) as auto:
+        client = Client(auto)
+        assert client.get_size() == len(passwords)
+        subtest_password_list(client, passwords)
+        # Converted entries require consent, as the default protection level
+        subtest_password_retrieval(client, auto, passwords)
+        assert client.get_metadata("want")['created'] == 0
+        # The storage is usable once converted
+        auto.actions = "rb"
+        client.add("new", "", "1")
+        assert client.get_size() == len(passwords) + 1
+
+def select_vault(auto, vault):
+    """
+    Select the active vault from the menu of the device, starting from the
+    idle screen and going back to it.
+    """
+    # The main menu starts with the version, the number of passwords and the
+    # active vault. Back is its eighth item.
+    auto.navigate("r" + "rrb" + "r" * vault + "b" + "r" * 7 + "b")
+
+def test_vaults():
+    """ Test that the entries of the other vaults cannot be accessed. """
+    with Automaton() as auto:
+        client = Client(auto)
+        for name, login, password in [("shared", "a", "1"), ("first", "b", "2")]:
+            auto.actions = "rb"
+            client.add(name, login, password)
+
+        select_vault(auto, 1)
+        assert client.get_size() == 0
+        assert client.get_names() == []
+        assert not client.has_name("first")
+        with pytest.raises(Exception):
+            client.get_by_name("first")
+        with pytest.raises(Exception):
+            client.delete_by_name("first")
+        # The same name can be used in each vault
+        auto.actions = "rb"
+        client.add("shared", "c", "3")
+        auto.actions = "rb"
+        assert client.get_by_name("shared") == ("c", "3")
+        # Clearing a vault does not affect the others
+        auto.actions = "bb"
+        client.clear()
+
+        select_vault(auto, 0)
+        assert sorted(client.get_names()) == ["first", "shared"]
+        auto.actions = "rb"
+        assert client.get_by_name("shared") == ("a", "1")
+        auto.actions = "rb"
+        assert client.get_by_name("first") == ("b", "2")
+
+# Mnemonic of another seed, standing for the seed derived with a decoy BIP39
+# passphrase.
+DECOY_SEED = "abandon " * 11 + "about"
+
+def test_decoy_seed(tmp_path, monkeypatch):
+    """
+    Test that the entries created with a seed cannot be accessed once the
+    device is unlocked with another one, such as a decoy passphrase.
+    """
+    # Keep the NVM data saved by speculos in the temporary directory.
+    monkeypatch.chdir(tmp_path)
+    app_path = tmp_path / "nanopass"
+    shutil.copy(APP_PATH, app_path)
+
+    with Automaton(str(app_path), ["--save-nvram"]) as auto:
+        client = Client(auto)
+        auto.actions = "rb"
+        client.add("real", "a", "1")
+
+    with Automaton(str(app_path),
+            ["--load-nvram", "--save-nvram", "--seed", DECOY_SEED]) as auto:
+        client = Client(auto)
+        assert client.get_size() == 0
+        assert not client.has_name("real")
+        with pytest.raises(Exception):
+            client.get_by_name("real")
+        with pytest.raises(Exception):
+            client.delete_by_name("real")
+        auto.actions = "rb"
+        client.add("decoy", "b", "2")
+        assert client.get_names() == ["decoy"]
+
+    with Automaton(str(app_path), ["--load-nvram"]) as auto:
+        client = Client(auto)
+        assert client.get_names() == ["real"]
+        auto.actions = "rb"
+        assert client.get_by_name("real") == ("a", "1")